@@ -8,11 +8,12 @@ use serde_json::json;
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
+use temperature_app::temperature::Celsius;
 use url::Url;
 
 /// The mutation query that we'll use to insert the data.
 const QUERY: &'static str = r#"
-    mutation ($address: String!, $temp: Celsius!) {
+    mutation ($address: BleAddress!, $temp: Celsius!) {
         addMeasurement(address:$address, tempC: $temp) {
             date
             tempC
@@ -21,6 +22,45 @@ const QUERY: &'static str = r#"
     }
 "#;
 
+/// A sensor to simulate, as given on the command line.
+struct DummySensor {
+    address: String,
+    min: Celsius,
+    max: Celsius,
+    period: Duration,
+}
+
+impl std::str::FromStr for DummySensor {
+    type Err = String;
+
+    /// Parse `ADDRESS,MIN,MAX,PERIOD`, where MIN and MAX are temperatures (in degrees celsius
+    /// unless they have a unit, like `62F`), and PERIOD is a number of seconds.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').collect();
+        if parts.len() != 4 {
+            return Err("Expected ADDRESS,MIN,MAX,PERIOD".into());
+        }
+
+        let min = parts[1]
+            .parse()
+            .map_err(|e| format!("Invalid minimum: {}", e))?;
+        let max = parts[2]
+            .parse()
+            .map_err(|e| format!("Invalid maximum: {}", e))?;
+        let period = parts[3]
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid period: {}", e))?;
+
+        Ok(DummySensor {
+            address: parts[0].trim().into(),
+            min,
+            max,
+            period: Duration::from_secs(period),
+        })
+    }
+}
+
 /// Start a thread that inserts dummy data into the system.
 ///
 /// This essentially inserts sine-wave-shaped data into the system. `min` is the trough of the sine
@@ -29,11 +69,16 @@ const QUERY: &'static str = r#"
 fn spawn_dummy(
     client: Arc<reqwest::Client>,
     url: Url,
-    address: String,
-    min: f64,
-    max: f64,
-    period: Duration,
+    sensor: DummySensor,
 ) -> thread::JoinHandle<()> {
+    let DummySensor {
+        address,
+        min,
+        max,
+        period,
+    } = sensor;
+    let (min, max) = (min.value(), max.value());
+
     thread::spawn(move || {
         let start = Instant::now();
         let sine_scale = 2.0 * std::f64::consts::PI / period.as_millis() as f64;
@@ -54,7 +99,7 @@ fn spawn_dummy(
                 }))
                 .send();
             match result {
-                Ok(_) => println!("{}: {}", address, value),
+                Ok(_) => println!("{}: {:.2}", address, Celsius::from(value)),
                 Err(e) => println!("{}", e),
             };
             thread::sleep(Duration::from_secs(2));
@@ -81,6 +126,21 @@ fn main() {
                 })
                 .default_value("http://127.0.0.1:8080/graphql"),
         )
+        .arg(
+            Arg::with_name("sensor")
+                .short("s")
+                .long("sensor")
+                .value_name("ADDRESS,MIN,MAX,PERIOD")
+                .help(
+                    "A sensor to simulate, whose temperature goes from MIN to MAX and back every \
+                     PERIOD seconds. Temperatures are in degrees celsius unless they have a unit, \
+                     like 62F. Defaults to the two sensors in sensors.toml",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|s| s.parse::<DummySensor>().map(|_| ())),
+        )
         .get_matches();
 
     // Get the database address for the ElasticSearch server to connect to from the command line.
@@ -89,26 +149,36 @@ fn main() {
 
     let client = Arc::new(reqwest::Client::new());
 
-    let child1 = spawn_dummy(
-        client.clone(),
-        url.clone(),
-        "f4d55889b1d6".into(),
-        16.667,                  // trough of sine wave
-        20.0,                    // crest of sine wave
-        Duration::from_secs(70), // period of sine wave
-    );
-
-    thread::sleep(Duration::from_secs(1));
-
-    let child2 = spawn_dummy(
-        client.clone(),
-        url.clone(),
-        "d0f7083ca3b1".into(),
-        24.88,                    // trough of sine wave
-        30.0,                     // crest of sine wave
-        Duration::from_secs(120), // period of sine wave
-    );
-
-    child1.join().unwrap();
-    child2.join().unwrap();
+    // We know all of these unwraps are valid because we had clap validate them for us already.
+    let sensors: Vec<DummySensor> = match matches.values_of("sensor") {
+        Some(sensors) => sensors.map(|sensor| sensor.parse().unwrap()).collect(),
+        None => vec![
+            DummySensor {
+                address: "f4d55889b1d6".into(),
+                min: 16.667.into(),              // trough of sine wave
+                max: 20.0.into(),                // crest of sine wave
+                period: Duration::from_secs(70), // period of sine wave
+            },
+            DummySensor {
+                address: "d0f7083ca3b1".into(),
+                min: 24.88.into(),                // trough of sine wave
+                max: 30.0.into(),                 // crest of sine wave
+                period: Duration::from_secs(120), // period of sine wave
+            },
+        ],
+    };
+
+    let children: Vec<thread::JoinHandle<()>> = sensors
+        .into_iter()
+        .map(|sensor| {
+            let child = spawn_dummy(client.clone(), url.clone(), sensor);
+            // Stagger the sensors, so they don't all report at once.
+            thread::sleep(Duration::from_secs(1));
+            child
+        })
+        .collect();
+
+    for child in children {
+        child.join().unwrap();
+    }
 }
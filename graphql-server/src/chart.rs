@@ -0,0 +1,292 @@
+//! Serving charts of temperature history, for places that can't run the homepage's JavaScript.
+//!
+//! `/chart.svg?address=f4d55889b1d6&from=2019-11-01T00:00:00Z&unit=F` draws one or more devices
+//! (separate addresses with commas) between `from` (a day ago, by default) and `to` (now, by
+//! default). Built with the `png` feature, `/chart.png` draws the same chart as a PNG.
+
+use chrono::{DateTime, Duration, Utc};
+use futures::Future;
+use serde::Deserialize;
+use temperature_app::{
+    address::BleAddress,
+    chart::{Chart, Line},
+    graphql::Context,
+    temperature::TemperatureUnit,
+};
+use warp::{http::Response, Rejection};
+
+/// How wide and tall charts are, unless asked otherwise.
+const DEFAULT_SIZE: (u32, u32) = (800, 300);
+
+/// The smallest and largest charts we'll draw, in pixels.
+const MIN_SIZE: u32 = 100;
+const MAX_SIZE: u32 = 4000;
+
+/// How many pixels wide each point of a line is. There's no use asking the database for more
+/// points than the chart has room for.
+const PIXELS_PER_POINT: u32 = 2;
+
+/// The query string for the chart routes
+#[derive(Debug, Deserialize)]
+pub struct ChartQuery {
+    /// The devices to draw, separated by commas
+    address: String,
+    /// Where the chart starts
+    from: Option<DateTime<Utc>>,
+    /// Where the chart ends
+    to: Option<DateTime<Utc>>,
+    /// The unit to draw temperatures in, like `F` or `celsius`
+    unit: Option<String>,
+    /// How wide the chart is, in pixels
+    width: Option<u32>,
+    /// How tall the chart is, in pixels
+    height: Option<u32>,
+}
+
+/// The kinds of images that a chart can be served as.
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    /// An SVG document
+    Svg,
+    /// A PNG image
+    #[cfg(feature = "png")]
+    Png,
+}
+
+/// Draw a chart.
+pub fn chart(
+    format: Format,
+    query: ChartQuery,
+    context: Context,
+) -> impl Future<Item = Response<Vec<u8>>, Error = Rejection> {
+    crate::blocking(move || render(format, &query, &context))
+}
+
+/// Draw a chart, or explain why we couldn't.
+fn render(format: Format, query: &ChartQuery, context: &Context) -> Response<Vec<u8>> {
+    let chart = match build(query, context) {
+        Ok(chart) => chart,
+        Err((status, message)) => {
+            return Response::builder()
+                .status(status)
+                .header("content-type", "text/plain; charset=utf-8")
+                .body(message.into_bytes())
+                .unwrap()
+        }
+    };
+
+    let svg = chart.to_svg();
+    match format {
+        Format::Svg => Response::builder()
+            .header("content-type", "image/svg+xml")
+            .body(svg.into_bytes())
+            .unwrap(),
+        #[cfg(feature = "png")]
+        Format::Png => match rasterize(&svg) {
+            Ok(png) => Response::builder()
+                .header("content-type", "image/png")
+                .body(png)
+                .unwrap(),
+            Err(message) => Response::builder()
+                .status(500)
+                .header("content-type", "text/plain; charset=utf-8")
+                .body(message.into_bytes())
+                .unwrap(),
+        },
+    }
+}
+
+/// Work out what to draw, and get it from the database. Problems come back as an HTTP status
+/// and a message.
+fn build(query: &ChartQuery, context: &Context) -> Result<Chart, (u16, String)> {
+    let addresses = query
+        .address
+        .split(',')
+        .map(str::trim)
+        .map(|address| {
+            address
+                .parse::<BleAddress>()
+                .map_err(|e| (400, format!("\"{}\" is not a BLE address: {}", address, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let until = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or_else(|| until - Duration::days(1));
+    if until <= from {
+        return Err((400, "A chart has to end after it starts".into()));
+    }
+
+    let unit: TemperatureUnit = match query.unit {
+        Some(ref unit) => unit.parse().map_err(|e| (400, format!("{}", e)))?,
+        None => context.unit,
+    };
+
+    let width = query.width.unwrap_or(DEFAULT_SIZE.0);
+    let height = query.height.unwrap_or(DEFAULT_SIZE.1);
+    for size in &[width, height] {
+        if !(MIN_SIZE..=MAX_SIZE).contains(size) {
+            return Err((
+                400,
+                format!(
+                    "Charts have to be between {} and {} pixels on each side",
+                    MIN_SIZE, MAX_SIZE
+                ),
+            ));
+        }
+    }
+
+    let points = width / PIXELS_PER_POINT;
+    let gap = (until - from) * 2 / points as i32;
+    let lines = addresses
+        .iter()
+        .map(|address| {
+            let series = context
+                .series(address, from, until, points)
+                .map_err(|e| (500, e.to_string()))?;
+            let name = context
+                .devices
+                .read()
+                .unwrap()
+                .get(address)
+                .and_then(|device| device.name.clone())
+                .unwrap_or_else(|| address.to_string());
+
+            Ok(Line {
+                name,
+                points: series
+                    .into_iter()
+                    .map(|bucket| (bucket.date, bucket.mean))
+                    .collect(),
+                gap: Some(gap),
+            })
+        })
+        .collect::<Result<Vec<_>, (u16, String)>>()?;
+
+    Ok(Chart {
+        from,
+        until,
+        unit,
+        width,
+        height,
+        lines,
+    })
+}
+
+/// Turn an SVG document into a PNG image.
+#[cfg(feature = "png")]
+fn rasterize(svg: &str) -> Result<Vec<u8>, String> {
+    use resvg::{tiny_skia, usvg};
+    use std::sync::{Arc, OnceLock};
+
+    // Finding the system's fonts is slow, so only do it once.
+    static FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+    let fonts = FONTS.get_or_init(|| {
+        let mut fonts = usvg::fontdb::Database::new();
+        fonts.load_system_fonts();
+        // The chart asks for sans-serif, which means Arial unless we say otherwise, and plenty of
+        // servers don't have Arial. Any sans-serif font will do.
+        let sans_serif = fonts
+            .faces()
+            .flat_map(|face| face.families.iter())
+            .map(|(family, _)| family.clone())
+            .find(|family| family == "Arial")
+            .or_else(|| {
+                fonts
+                    .faces()
+                    .flat_map(|face| face.families.iter())
+                    .map(|(family, _)| family.clone())
+                    .find(|family| family.contains("Sans") && !family.contains("Mono"))
+            });
+        if let Some(sans_serif) = sans_serif {
+            fonts.set_sans_serif_family(sans_serif);
+        }
+        Arc::new(fonts)
+    });
+
+    let options = usvg::Options {
+        fontdb: fonts.clone(),
+        ..usvg::Options::default()
+    };
+    let tree = usvg::Tree::from_str(svg, &options).map_err(|e| e.to_string())?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| "The chart is too big to draw".to_string())?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.encode_png().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(address: &str) -> ChartQuery {
+        ChartQuery {
+            address: address.into(),
+            from: Some("2019-11-01T00:00:00Z".parse().unwrap()),
+            to: Some("2019-11-02T00:00:00Z".parse().unwrap()),
+            unit: None,
+            width: None,
+            height: None,
+        }
+    }
+
+    /// The status and message of a chart that couldn't be built.
+    fn error(query: &ChartQuery) -> (u16, String) {
+        match build(query, &crate::offline_context()) {
+            Ok(_) => panic!("expected the chart to fail"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn bad_addresses() {
+        let (status, message) = error(&query("f4d55889b1d6, nope"));
+        assert_eq!(status, 400);
+        assert!(
+            message.starts_with("\"nope\" is not a BLE address"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn backwards_range() {
+        let mut query = query("f4d55889b1d6");
+        query.to = query.from;
+        assert_eq!(
+            error(&query),
+            (400, "A chart has to end after it starts".into())
+        );
+    }
+
+    #[test]
+    fn bad_unit() {
+        let mut query = query("f4d55889b1d6");
+        query.unit = Some("rankine".into());
+        assert_eq!(error(&query).0, 400);
+    }
+
+    #[test]
+    fn bad_sizes() {
+        for &(width, height) in &[(MIN_SIZE - 1, 300), (800, MAX_SIZE + 1)] {
+            let mut query = query("f4d55889b1d6");
+            query.width = Some(width);
+            query.height = Some(height);
+            assert_eq!(error(&query).0, 400);
+        }
+    }
+
+    #[test]
+    fn database_down() {
+        let response = render(
+            Format::Svg,
+            &query("f4d55889b1d6"),
+            &crate::offline_context(),
+        );
+        assert_eq!(response.status(), 500);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+    }
+}
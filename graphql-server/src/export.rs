@@ -0,0 +1,234 @@
+//! Downloading measurement history as spreadsheets.
+//!
+//! `/export?address=f4d55889b1d6,d0f7083ca3b1&from=2019-11-01T00:00:00Z&format=csv` streams every
+//! measurement the devices took between `from` (a day ago, by default) and `to` (now, by default)
+//! as CSV, or as newline-delimited JSON with `format=ndjson`. The same export is available from
+//! the command line with `graphql-server export`.
+
+use chrono::{DateTime, Duration, Utc};
+use futures::{
+    future::{self, Either},
+    stream, Async, Future, Stream,
+};
+use serde::Deserialize;
+use std::io::Write;
+use temperature_app::{
+    address::BleAddress,
+    database::DatabaseError,
+    export::{Export, ExportFormat},
+    graphql::Context,
+};
+use warp::{http::Response, Rejection};
+
+/// The most rows to send in one chunk. Sending each row on its own would mean a lot of tiny
+/// writes, and collecting the whole export would mean holding it all in memory.
+const ROWS_PER_CHUNK: usize = 500;
+
+/// Why a download was cut short
+type BodyError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The query string for the `/export` route
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// The devices to export, separated by commas
+    address: String,
+    /// The earliest measurements to export
+    from: Option<DateTime<Utc>>,
+    /// The latest measurements to export
+    to: Option<DateTime<Utc>>,
+    /// `csv` (the default) or `ndjson`
+    format: Option<String>,
+}
+
+/// Stream an export to the client.
+pub fn export(
+    query: ExportQuery,
+    context: Context,
+) -> impl Future<Item = Response<hyper::Body>, Error = Rejection> {
+    let (format, export) = match build(&query, &context) {
+        Ok(export) => export,
+        Err(message) => return Either::A(future::ok(text(400, message))),
+    };
+
+    // Read the first chunk before answering, so that if the database is down the client gets an
+    // error instead of an empty spreadsheet. After that, it's too late to change the status, so a
+    // failure just cuts the download short.
+    Either::B(
+        crate::blocking(move || {
+            let mut export = export;
+            chunk(format, &mut export).map(|first| (first, export))
+        })
+        .map(move |result| {
+            let (first, mut export) = match result {
+                Ok(result) => result,
+                Err(e) => return text(500, e.to_string()),
+            };
+
+            let first =
+                format.header().unwrap_or_default().to_string() + &first.unwrap_or_default();
+            let rest = stream::poll_fn(move || -> Result<_, BodyError> {
+                match tokio_threadpool::blocking(|| chunk(format, &mut export)) {
+                    Ok(Async::Ready(result)) => {
+                        Ok(Async::Ready(result.map_err(|e| e.to_string())?))
+                    }
+                    Ok(Async::NotReady) => Ok(Async::NotReady),
+                    Err(e) => Err(e.into()),
+                }
+            });
+            let body = stream::once(Ok(first)).chain(rest);
+
+            Response::builder()
+                .header("content-type", format.content_type())
+                .header(
+                    "content-disposition",
+                    format!(
+                        "attachment; filename=\"measurements.{}\"",
+                        format.extension()
+                    ),
+                )
+                .body(hyper::Body::wrap_stream(body))
+                .unwrap()
+        }),
+    )
+}
+
+/// Work out what to export. Problems come back as a message for the client.
+fn build(query: &ExportQuery, context: &Context) -> Result<(ExportFormat, Export), String> {
+    let addresses = parse_addresses(&query.address)?;
+
+    let until = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or_else(|| until - Duration::days(1));
+    if until < from {
+        return Err("An export has to end after it starts".into());
+    }
+
+    let format = match query.format {
+        Some(ref format) => format.parse().map_err(|e| format!("{}", e))?,
+        None => ExportFormat::Csv,
+    };
+
+    let export = Export::new(
+        context.database.clone(),
+        &addresses,
+        &context.devices.read().unwrap(),
+        from,
+        until,
+    );
+    Ok((format, export))
+}
+
+/// Parse a comma-separated list of addresses.
+fn parse_addresses(addresses: &str) -> Result<Vec<BleAddress>, String> {
+    addresses
+        .split(',')
+        .map(str::trim)
+        .map(|address| {
+            address
+                .parse::<BleAddress>()
+                .map_err(|e| format!("\"{}\" is not a BLE address: {}", address, e))
+        })
+        .collect()
+}
+
+/// Write out the next few rows of an export, or `None` if it's finished.
+fn chunk(format: ExportFormat, export: &mut Export) -> Result<Option<String>, DatabaseError> {
+    let mut chunk = String::new();
+    for row in export.by_ref().take(ROWS_PER_CHUNK) {
+        chunk.push_str(&format.line(&row?));
+    }
+
+    if chunk.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(chunk))
+    }
+}
+
+/// Write a whole export to something, like standard output, and return how many rows there were.
+/// Database errors come back as I/O errors, so that there's only one kind of error to handle.
+pub fn write(format: ExportFormat, export: Export, mut out: impl Write) -> std::io::Result<u64> {
+    let mut rows = 0;
+    if let Some(header) = format.header() {
+        out.write_all(header.as_bytes())?;
+    }
+    for row in export {
+        let row = row.map_err(|e| std::io::Error::other(e.to_string()))?;
+        out.write_all(format.line(&row).as_bytes())?;
+        rows += 1;
+    }
+    out.flush()?;
+
+    Ok(rows)
+}
+
+/// A plain-text response, for explaining what went wrong.
+fn text(status: u16, message: String) -> Response<hyper::Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "text/plain; charset=utf-8")
+        .body(message.into())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(address: &str) -> ExportQuery {
+        ExportQuery {
+            address: address.into(),
+            from: Some("2019-11-01T00:00:00Z".parse().unwrap()),
+            to: Some("2019-11-02T00:00:00Z".parse().unwrap()),
+            format: None,
+        }
+    }
+
+    /// The message for an export that couldn't be built.
+    fn error(query: &ExportQuery) -> String {
+        match build(query, &crate::offline_context()) {
+            Ok(_) => panic!("expected the export to fail"),
+            Err(message) => message,
+        }
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            parse_addresses("f4d55889b1d6, D0:F7:08:3C:A3:B1")
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["f4d55889b1d6", "d0f7083ca3b1"]
+        );
+        assert!(error(&query("f4d55889b1d6,")).starts_with("\"\" is not a BLE address"));
+    }
+
+    #[test]
+    fn backwards_range() {
+        let mut query = query("f4d55889b1d6");
+        query.from = Some("2019-11-03T00:00:00Z".parse().unwrap());
+        assert_eq!(error(&query), "An export has to end after it starts");
+    }
+
+    #[test]
+    fn formats() {
+        let mut query = query("f4d55889b1d6");
+        query.format = Some("ndjson".into());
+        let (format, _) = build(&query, &crate::offline_context()).unwrap();
+        assert_eq!(format, ExportFormat::Ndjson);
+
+        query.format = Some("xlsx".into());
+        assert!(build(&query, &crate::offline_context()).is_err());
+    }
+
+    #[test]
+    fn database_down() {
+        let (format, export) = build(&query("f4d55889b1d6"), &crate::offline_context()).unwrap();
+        let mut out = Vec::new();
+        let error = write(format, export, &mut out).unwrap_err();
+        assert!(error.to_string().contains("database"), "{}", error);
+        // The header was already written by the time the database was asked for any rows.
+        assert!(String::from_utf8(out).unwrap().starts_with("date,address,"));
+    }
+}
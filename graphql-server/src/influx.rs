@@ -0,0 +1,184 @@
+//! Taking measurements written in InfluxDB line protocol.
+//!
+//! Telegraf, ESPHome bridges, and anything else that can write to InfluxDB 1.x can point at this
+//! server instead. `/write?precision=s` takes one reading per line; see the
+//! [`line_protocol`] module for what a line looks like. Answers
+//! look like InfluxDB's: no content when every line was stored, and a JSON `error` otherwise.
+//!
+//! Each request is stored with [`Context::add_measurements`], so clients can retry one that partly
+//! failed.
+
+use chrono::Utc;
+use futures::Future;
+use serde::Deserialize;
+use temperature_app::{
+    database::InsertResult,
+    graphql::Context,
+    line_protocol::{self, Precision},
+};
+use warp::{http::Response, Buf, Rejection};
+
+/// The biggest request we'll accept. Telegraf sends 1000 lines at a time by default, which is far
+/// smaller than this.
+pub const MAX_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The query string for the `/write` route. InfluxDB's `db` and `rp` parameters are accepted, but
+/// there's only one place for measurements to go, so they're ignored.
+#[derive(Debug, Deserialize)]
+pub struct WriteQuery {
+    /// The unit of the lines' timestamps: `n` (the default), `u`, `ms`, `s`, `m`, or `h`
+    precision: Option<String>,
+}
+
+/// Store the readings in a write request.
+pub fn write(
+    query: WriteQuery,
+    body: warp::body::FullBody,
+    context: Context,
+) -> impl Future<Item = Response<String>, Error = Rejection> {
+    let body = body.bytes().to_vec();
+
+    crate::blocking(move || store(&query, &body, &context)).map(respond)
+}
+
+/// Answer a write request the way InfluxDB would.
+fn respond(result: Result<(), (u16, String)>) -> Response<String> {
+    match result {
+        Ok(()) => Response::builder().status(204).body(String::new()).unwrap(),
+        // Like InfluxDB, the error is in a header as well as the body, and clients retry server
+        // errors but not client errors.
+        Err((status, error)) => Response::builder()
+            .status(status)
+            .header("content-type", "application/json")
+            .header(
+                "x-influxdb-error",
+                // Headers can't have newlines, or whatever else the lines had in them.
+                error
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .collect::<String>(),
+            )
+            .body(serde_json::json!({ "error": error }).to_string())
+            .unwrap(),
+    }
+}
+
+/// Parse a request and store every line that can be stored. Like InfluxDB, lines that can't be
+/// read don't stop the others from being stored, but they are reported. Problems come back as an
+/// HTTP status and an error message.
+fn store(query: &WriteQuery, body: &[u8], context: &Context) -> Result<(), (u16, String)> {
+    let precision = match query.precision {
+        Some(ref precision) => precision.parse().map_err(|e| (400, format!("{}", e)))?,
+        None => Precision::default(),
+    };
+    let body = std::str::from_utf8(body)
+        .map_err(|_| (400, "unable to parse request: not UTF-8".to_string()))?;
+
+    let now = Utc::now();
+    let mut unparsable = Vec::new();
+    let mut measurements = Vec::new();
+    for (line, point) in line_protocol::parse(body) {
+        let measurement = match point.and_then(|point| point.new_measurement(precision, now)) {
+            Ok(measurement) => measurement,
+            Err(e) => {
+                unparsable.push(format!("unable to parse '{}': {}", line, e));
+                continue;
+            }
+        };
+
+        measurements.push(measurement);
+    }
+
+    // If the database is down, the client should try again later.
+    let results = context
+        .add_measurements(measurements)
+        .map_err(|e| (500, e.to_string()))?;
+
+    // Like InfluxDB, writing a point that's already there isn't a problem.
+    let mut dropped = Vec::new();
+    for result in results {
+        match result {
+            Ok(InsertResult::Inserted) | Ok(InsertResult::Duplicate) => {}
+            Ok(InsertResult::Failed(reason)) => dropped.push(reason),
+            Err(e) => dropped.push(e.to_string()),
+        }
+    }
+
+    if !unparsable.is_empty() {
+        Err((400, unparsable.join("\n")))
+    } else if let Some(first) = dropped.first() {
+        Err((
+            400,
+            format!("partial write: {} dropped={}", first, dropped.len()),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The response to a request with the default precision.
+    fn write(body: &str) -> Response<String> {
+        let query = WriteQuery { precision: None };
+        respond(store(&query, body.as_bytes(), &crate::offline_context()))
+    }
+
+    #[test]
+    fn nothing_to_store() {
+        let response = write("# Just a comment\n\n");
+        assert_eq!(response.status(), 204);
+        assert_eq!(response.body(), "");
+    }
+
+    #[test]
+    fn unreadable_lines() {
+        let response = write("temperature,address=f4d55889b1d6 temp_c=warm\n");
+        assert_eq!(response.status(), 400);
+        assert_eq!(
+            response.headers()["x-influxdb-error"],
+            "unable to parse 'temperature,address=f4d55889b1d6 temp_c=warm': \
+             invalid field value: warm"
+        );
+        assert_eq!(response.headers()["content-type"], "application/json");
+    }
+
+    #[test]
+    fn several_unreadable_lines_fit_in_a_header() {
+        let response = write("nope\nnope\n");
+        assert_eq!(response.status(), 400);
+        let header = response.headers()["x-influxdb-error"].to_str().unwrap();
+        assert!(!header.contains('\n'), "{}", header);
+        let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(body["error"].as_str().unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn impossible_readings_are_dropped() {
+        let response = write("temperature,address=f4d55889b1d6 temp_c=-300 1572609600\n");
+        assert_eq!(response.status(), 400);
+        // The reason has a degree sign in it, which headers can carry but not as a `&str`.
+        let error = String::from_utf8_lossy(response.headers()["x-influxdb-error"].as_bytes());
+        assert!(error.starts_with("partial write: "), "{}", error);
+        assert!(error.ends_with(" dropped=1"), "{}", error);
+    }
+
+    #[test]
+    fn database_errors_are_retried() {
+        let response = write("temperature,address=f4d55889b1d6 temp_c=20 1572609600000000000\n");
+        assert_eq!(response.status(), 500);
+        assert!(response.headers().contains_key("x-influxdb-error"));
+    }
+
+    #[test]
+    fn unknown_precision() {
+        let query = WriteQuery {
+            precision: Some("d".into()),
+        };
+        let (status, message) = store(&query, b"", &crate::offline_context()).unwrap_err();
+        assert_eq!(status, 400);
+        assert_eq!(message, "invalid precision \"d\"");
+    }
+}
@@ -0,0 +1,124 @@
+//! Serving metrics to Prometheus.
+//!
+//! `/metrics` describes how the server is doing (how many GraphQL requests it has answered and
+//! how long they took, and how long requests to ElasticSearch took and how many failed), along
+//! with the latest temperature of every device in the registry, labeled with its address and
+//! name.
+
+use futures::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use temperature_app::{
+    graphql::Context,
+    metrics::{write_gauge, Counter, Histogram},
+};
+use warp::{http::Response, Rejection};
+
+/// What the server keeps track of itself. The database keeps track of its own requests.
+pub struct Metrics {
+    graphql_requests: Counter,
+    graphql_durations: Histogram,
+}
+
+impl Metrics {
+    /// Start counting from zero.
+    pub fn new() -> Self {
+        Metrics {
+            graphql_requests: Counter::new(
+                "temperature_app_graphql_requests_total",
+                "How many GraphQL requests have been answered, by HTTP status",
+            ),
+            graphql_durations: Histogram::new(
+                "temperature_app_graphql_request_duration_seconds",
+                "How long GraphQL requests took to answer",
+            ),
+        }
+    }
+
+    /// Count a GraphQL request that has been answered.
+    pub fn observe_graphql(&self, status: u16, duration: Duration) {
+        self.graphql_requests
+            .inc(&[("status", &status.to_string())]);
+        self.graphql_durations.observe_duration(&[], duration);
+    }
+}
+
+/// Answer a scrape.
+pub fn metrics(
+    metrics: Arc<Metrics>,
+    context: Context,
+) -> impl Future<Item = Response<String>, Error = Rejection> {
+    crate::blocking(move || render(&metrics, &context)).map(|body| {
+        Response::builder()
+            .header("content-type", "text/plain; version=0.0.4; charset=utf-8")
+            .body(body)
+            .unwrap()
+    })
+}
+
+/// Write out every metric in the Prometheus text format.
+fn render(metrics: &Metrics, context: &Context) -> String {
+    let mut out = String::new();
+
+    // If the database is down, the rest of the metrics are still worth having, and the `up`
+    // gauge says why the temperatures are missing.
+    let events = context.current_measurement_events();
+    write_gauge(
+        &mut out,
+        "temperature_app_database_up",
+        "Whether the latest temperatures could be read from ElasticSearch",
+        &[(&[], if events.is_ok() { 1.0 } else { 0.0 })],
+    );
+
+    let events = events.unwrap_or_default();
+    let labels: Vec<[(&str, &str); 2]> = events
+        .iter()
+        .map(|event| {
+            [
+                ("address", event.address.as_str()),
+                ("name", event.name.as_deref().unwrap_or("")),
+            ]
+        })
+        .collect();
+    write_gauge(
+        &mut out,
+        "temperature_app_device_temperature_celsius",
+        "The latest temperature of each device, with its calibration applied",
+        &events
+            .iter()
+            .zip(&labels)
+            .map(|(event, labels)| (&labels[..], event.temp_c))
+            .collect::<Vec<_>>(),
+    );
+    write_gauge(
+        &mut out,
+        "temperature_app_device_last_measurement_timestamp_seconds",
+        "When each device's latest measurement was taken, in seconds since the UNIX epoch",
+        &events
+            .iter()
+            .zip(&labels)
+            .map(|(event, labels)| (&labels[..], event.date.timestamp() as f64))
+            .collect::<Vec<_>>(),
+    );
+
+    metrics.graphql_requests.write(&mut out);
+    metrics.graphql_durations.write(&mut out);
+    context.database.write_metrics(&mut out);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_down_with_no_devices() {
+        let context = crate::offline_context();
+        context.devices.write().unwrap().clear();
+
+        let out = render(&Metrics::new(), &context);
+        assert!(out.contains("\ntemperature_app_database_up 0\n"));
+        assert!(out.contains("# TYPE temperature_app_graphql_requests_total counter\n"));
+    }
+}
@@ -0,0 +1,315 @@
+//! Taking measurements from Prometheus remote write.
+//!
+//! Sensors that are already scraped by Prometheus (or anything else that speaks its remote write
+//! protocol) can send their readings here instead of to `addMeasurement`. Point `remote_write` at
+//! `/api/v1/write`, and start the server with `--remote-write-metric` naming the metric that holds
+//! temperatures. Each sample of that metric is checked as `addMeasurement` would check it, and
+//! stored as a measurement of the device in its address label. Samples of other metrics are
+//! ignored.
+//!
+//! Each request is stored with [`Context::add_measurements`], so Prometheus can retry one that
+//! partly failed.
+
+use chrono::{TimeZone, Utc};
+use futures::Future;
+use std::sync::Arc;
+use temperature_app::{
+    address::BleAddress,
+    channels::Channels,
+    database::{InsertResult, NewMeasurement},
+    graphql::Context,
+    temperature::TemperatureUnit,
+};
+use warp::{http::Response, Buf, Rejection};
+
+/// The biggest request we'll accept, compressed or not. Prometheus sends a few thousand samples
+/// at a time by default, which is far smaller than this.
+pub const MAX_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Which samples are temperatures, and how to read them.
+#[derive(Debug, Clone)]
+pub struct RemoteWriteConfig {
+    /// The name of the metric with the temperatures
+    pub metric: String,
+    /// The label with the device's BLE address
+    pub address_label: String,
+    /// The unit that the temperatures are in
+    pub unit: TemperatureUnit,
+}
+
+/// A remote write request. These are the parts of Prometheus' `prompb.WriteRequest` that we need;
+/// anything else that's sent is skipped over.
+#[derive(Clone, PartialEq, prost::Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+/// A single time series: its labels, including its name as `__name__`, and its samples.
+#[derive(Clone, PartialEq, prost::Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+/// A label name and value.
+#[derive(Clone, PartialEq, prost::Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+/// A value, and when it was taken in milliseconds since the UNIX epoch.
+#[derive(Clone, PartialEq, prost::Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+/// Store the temperatures in a remote write request.
+pub fn remote_write(
+    config: Arc<RemoteWriteConfig>,
+    body: warp::body::FullBody,
+    context: Context,
+) -> impl Future<Item = Response<String>, Error = Rejection> {
+    let body = body.bytes().to_vec();
+
+    crate::blocking(move || store(&config, &body, &context)).map(|result| {
+        // Prometheus retries server errors, but gives up on client errors, which is what we
+        // want: a request that can't be read now never will be.
+        let (status, message) = match result {
+            Ok(message) => (200, message),
+            Err(e) => e,
+        };
+        Response::builder()
+            .status(status)
+            .header("content-type", "text/plain; charset=utf-8")
+            .body(message)
+            .unwrap()
+    })
+}
+
+/// Decode a request and store its temperatures. Problems come back as an HTTP status and a
+/// message.
+fn store(
+    config: &RemoteWriteConfig,
+    body: &[u8],
+    context: &Context,
+) -> Result<String, (u16, String)> {
+    let body = snap::raw::Decoder::new()
+        .decompress_vec(body)
+        .map_err(|e| (400, format!("The request is not snappy-compressed: {}", e)))?;
+    let request: WriteRequest = prost::Message::decode(&body[..]).map_err(|e| {
+        (
+            400,
+            format!("The request is not a remote write request: {}", e),
+        )
+    })?;
+
+    let mut measurements = Vec::new();
+    let mut skipped = Vec::new();
+    for series in &request.timeseries {
+        let label = |name: &str| {
+            series
+                .labels
+                .iter()
+                .find(|label| label.name == name)
+                .map(|label| label.value.as_str())
+        };
+        if label("__name__") != Some(config.metric.as_str()) {
+            continue;
+        }
+
+        let address = match label(&config.address_label).map(str::parse::<BleAddress>) {
+            Some(Ok(address)) => address,
+            Some(Err(e)) => {
+                skipped.push(format!("{}: {}", config.metric, e));
+                continue;
+            }
+            None => {
+                skipped.push(format!(
+                    "{} has no {} label",
+                    config.metric, config.address_label
+                ));
+                continue;
+            }
+        };
+
+        for sample in &series.samples {
+            // Prometheus marks series that have gone away with a NaN, which isn't a reading.
+            if sample.value.is_nan() {
+                continue;
+            }
+            let date = match Utc.timestamp_millis_opt(sample.timestamp).single() {
+                Some(date) => date,
+                None => {
+                    skipped.push(format!(
+                        "{}: {} is not a timestamp",
+                        address, sample.timestamp
+                    ));
+                    continue;
+                }
+            };
+
+            measurements.push(NewMeasurement {
+                address: address.clone(),
+                date,
+                temperature: config.unit.to_celsius(sample.value),
+                channels: Channels::default(),
+            });
+        }
+    }
+
+    let addresses: Vec<BleAddress> = measurements
+        .iter()
+        .map(|measurement| measurement.address.clone())
+        .collect();
+    // If the database is down, Prometheus should try again later.
+    let results = context
+        .add_measurements(measurements)
+        .map_err(|e| (500, e.to_string()))?;
+
+    let mut stored = 0;
+    let mut duplicates = 0;
+    for (address, result) in addresses.iter().zip(results) {
+        match result {
+            Ok(InsertResult::Inserted) => stored += 1,
+            Ok(InsertResult::Duplicate) => duplicates += 1,
+            Ok(InsertResult::Failed(reason)) => skipped.push(format!("{}: {}", address, reason)),
+            Err(e) => skipped.push(format!("{}: {}", address, e)),
+        }
+    }
+
+    if let Some(first) = skipped.first() {
+        eprintln!(
+            "Skipped {} remote write samples. The first was skipped because: {}",
+            skipped.len(),
+            first
+        );
+    }
+    Ok(format!(
+        "Stored {}, already stored {}, skipped {}",
+        stored,
+        duplicates,
+        skipped.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RemoteWriteConfig {
+        RemoteWriteConfig {
+            metric: "room_temperature_celsius".into(),
+            address_label: "address".into(),
+            unit: TemperatureUnit::Celsius,
+        }
+    }
+
+    /// A compressed request with one series of samples.
+    fn request(labels: &[(&str, &str)], samples: &[f64]) -> Vec<u8> {
+        let request = WriteRequest {
+            timeseries: vec![TimeSeries {
+                labels: labels
+                    .iter()
+                    .map(|&(name, value)| Label {
+                        name: name.into(),
+                        value: value.into(),
+                    })
+                    .collect(),
+                samples: samples
+                    .iter()
+                    .map(|&value| Sample {
+                        value,
+                        timestamp: 1_572_609_600_000,
+                    })
+                    .collect(),
+            }],
+        };
+        snap::raw::Encoder::new()
+            .compress_vec(&prost::Message::encode_to_vec(&request))
+            .unwrap()
+    }
+
+    #[test]
+    fn uncompressed_request() {
+        let (status, message) =
+            store(&config(), b"not snappy", &crate::offline_context()).unwrap_err();
+        assert_eq!(status, 400);
+        assert!(message.starts_with("The request is not snappy-compressed"));
+    }
+
+    #[test]
+    fn not_a_write_request() {
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&[0xff, 0xff, 0xff])
+            .unwrap();
+        let (status, message) = store(&config(), &body, &crate::offline_context()).unwrap_err();
+        assert_eq!(status, 400);
+        assert!(message.starts_with("The request is not a remote write request"));
+    }
+
+    #[test]
+    fn other_metrics_are_ignored() {
+        let body = request(&[("__name__", "up"), ("address", "f4d55889b1d6")], &[1.0]);
+        assert_eq!(
+            store(&config(), &body, &crate::offline_context()),
+            Ok("Stored 0, already stored 0, skipped 0".into())
+        );
+    }
+
+    #[test]
+    fn unusable_samples_are_skipped() {
+        let context = crate::offline_context();
+        let missing = request(&[("__name__", "room_temperature_celsius")], &[20.0]);
+        assert_eq!(
+            store(&config(), &missing, &context),
+            Ok("Stored 0, already stored 0, skipped 1".into())
+        );
+
+        let invalid = request(
+            &[
+                ("__name__", "room_temperature_celsius"),
+                ("address", "nope"),
+            ],
+            &[20.0],
+        );
+        assert_eq!(
+            store(&config(), &invalid, &context),
+            Ok("Stored 0, already stored 0, skipped 1".into())
+        );
+
+        // Stale markers are left out without complaint, and impossible temperatures are skipped.
+        let stale = request(
+            &[
+                ("__name__", "room_temperature_celsius"),
+                ("address", "f4d55889b1d6"),
+            ],
+            &[f64::NAN, -300.0],
+        );
+        assert_eq!(
+            store(&config(), &stale, &context),
+            Ok("Stored 0, already stored 0, skipped 1".into())
+        );
+    }
+
+    #[test]
+    fn database_errors_are_retried() {
+        let body = request(
+            &[
+                ("__name__", "room_temperature_celsius"),
+                ("address", "f4d55889b1d6"),
+            ],
+            &[20.0],
+        );
+        let (status, _) = store(&config(), &body, &crate::offline_context()).unwrap_err();
+        assert_eq!(status, 500);
+    }
+}
@@ -0,0 +1,876 @@
+//! Reading (and re-reading) the sensors.toml file.
+
+use chrono::{DateTime, NaiveDate, SubsecRound, TimeZone, Utc};
+use serde::Deserialize;
+use signal_hook::SIGHUP;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use temperature_app::{
+    address::BleAddress,
+    calibration::{self, Calibration, CalibrationError, CalibrationHistory, DatedCalibration},
+    database::Database,
+    graphql::Device,
+    temperature::{Celsius, CelsiusDelta, OutOfRangeAction, PlausibleRange},
+};
+use toml::Spanned;
+
+/// How often to check whether sensors.toml has changed.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The structure that represents the sensors.toml file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    sensors: Vec<ConfigSensor>,
+}
+
+/// A single sensor in the sensors.toml file
+///
+/// The values we validate are spanned, so that we can point at them when they're wrong.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigSensor {
+    address: Spanned<String>,
+    name: Option<String>,
+    description: Option<String>,
+    adjustment: Option<Spanned<CelsiusDelta>>,
+    calibration: Option<ConfigCalibration>,
+    calibrations: Option<Vec<ConfigCalibration>>,
+    plausible_range: Option<ConfigPlausibleRange>,
+}
+
+/// The calibration of a single sensor in the sensors.toml file, for sensors that need more than
+/// a fixed adjustment. Either `gain` (and optionally `offset`) for a linear calibration, or
+/// `points` for a table of `[raw, actual]` pairs to interpolate between.
+///
+/// A sensor that has been recalibrated lists each of its calibrations in `calibrations`, with
+/// the date that they took effect in `effective_from`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigCalibration {
+    effective_from: Option<Spanned<toml::value::Datetime>>,
+    gain: Option<Spanned<f64>>,
+    offset: Option<Spanned<CelsiusDelta>>,
+    points: Option<Spanned<Vec<(Celsius, Celsius)>>>,
+}
+
+/// The temperatures that a sensor can believably report. Readings outside of the range are
+/// rejected, unless `action` is `"flag"`, in which case they are stored but marked implausible.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigPlausibleRange {
+    min: Option<Spanned<Celsius>>,
+    max: Option<Spanned<Celsius>>,
+    action: Option<OutOfRangeAction>,
+}
+
+/// A single problem with a sensors.toml file, and where in the file it is.
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// The line of the problem, starting at 1
+    pub line: usize,
+    /// The column of the problem, starting at 1
+    pub column: usize,
+    /// What's wrong
+    pub message: String,
+}
+
+/// Errors that can occur when loading a sensors.toml file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read.
+    Io(PathBuf, std::io::Error),
+    /// The file was read, but it has problems.
+    Invalid(PathBuf, Vec<Diagnostic>),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Invalid(path, diagnostics) => {
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(
+                        f,
+                        "{}:{}:{}: {}",
+                        path.display(),
+                        diagnostic.line,
+                        diagnostic.column,
+                        diagnostic.message
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ConfigFile {
+    /// The devices described by the file.
+    ///
+    /// Sensors with malformed addresses are left out, but a file that got through
+    /// [`load_sensors`] doesn't have any of those.
+    pub fn devices(&self) -> Vec<Device> {
+        self.sensors
+            .iter()
+            .filter_map(|sensor| {
+                let address = sensor.address.get_ref().parse().ok()?;
+                Some((sensor, address))
+            })
+            .map(|(sensor, address)| Device {
+                address,
+                name: sensor.name.clone(),
+                description: sensor.description.clone(),
+                calibrations: sensor.calibrations().unwrap_or_default(),
+                plausible_range: sensor
+                    .plausible_range
+                    .as_ref()
+                    .and_then(ConfigPlausibleRange::plausible_range),
+            })
+            .collect()
+    }
+
+    /// Make sure the file makes sense as a whole, beyond just being parseable. Every problem we
+    /// find is reported, not just the first one.
+    fn validate(&self, contents: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut seen = BTreeMap::new();
+
+        for sensor in &self.sensors {
+            // Addresses are compared in their canonical form, so that "d0f7083ca3b1" and
+            // "D0:F7:08:3C:A3:B1" count as the same sensor.
+            match sensor.address.get_ref().parse::<BleAddress>() {
+                Ok(address) => match seen.get(&address) {
+                    Some(first) => diagnostics.push(diagnostic(
+                        contents,
+                        sensor.address.start(),
+                        format!(
+                            "Sensor {} is listed more than once (first at line {})",
+                            address,
+                            line_column(contents, *first).0
+                        ),
+                    )),
+                    None => {
+                        seen.insert(address, sensor.address.start());
+                    }
+                },
+                Err(e) => diagnostics.push(diagnostic(
+                    contents,
+                    sensor.address.start(),
+                    format!(
+                        "\"{}\" is not a BLE address: {}",
+                        sensor.address.get_ref(),
+                        e
+                    ),
+                )),
+            }
+
+            if let Some(ref adjustment) = sensor.adjustment {
+                if let Err(e) = calibration::check_offset(*adjustment.get_ref()) {
+                    diagnostics.push(diagnostic(contents, adjustment.start(), e.to_string()));
+                }
+            }
+
+            let kinds = [
+                sensor.adjustment.is_some(),
+                sensor.calibration.is_some(),
+                sensor.calibrations.is_some(),
+            ];
+            if kinds.iter().filter(|kind| **kind).count() > 1 {
+                diagnostics.push(diagnostic(
+                    contents,
+                    sensor.address.start(),
+                    "A sensor can only have one of adjustment, calibration, and calibrations"
+                        .into(),
+                ));
+            }
+
+            // TOML doesn't give us a useful span for a calibration written as its own
+            // [sensors.calibration] table, so point at something inside it (or at the sensor)
+            // instead.
+            let mut effective = BTreeMap::new();
+            for calibration in sensor.all_calibrations() {
+                let start = calibration
+                    .start()
+                    .unwrap_or_else(|| sensor.address.start());
+                calibration.validate(contents, start, &mut diagnostics);
+
+                if let Ok(effective_from) = calibration.effective_from() {
+                    if effective.insert(effective_from, start).is_some() {
+                        diagnostics.push(diagnostic(
+                            contents,
+                            start,
+                            CalibrationError::DuplicateEffectiveFrom(effective_from).to_string(),
+                        ));
+                    }
+                }
+            }
+
+            if let Some(ref plausible_range) = sensor.plausible_range {
+                plausible_range.validate(contents, sensor.address.start(), &mut diagnostics);
+            }
+        }
+
+        diagnostics
+    }
+}
+
+impl ConfigSensor {
+    /// Every calibration listed for the sensor, whether in `calibration` or `calibrations`.
+    fn all_calibrations(&self) -> impl Iterator<Item = &ConfigCalibration> {
+        self.calibration
+            .iter()
+            .chain(self.calibrations.iter().flatten())
+    }
+
+    /// The calibration history described by the sensor's adjustment or calibrations, if it has
+    /// one.
+    fn calibrations(&self) -> Option<CalibrationHistory> {
+        if self.calibration.is_none() && self.calibrations.is_none() {
+            let adjustment = *self.adjustment.as_ref()?.get_ref();
+            return Some(Calibration::Offset(adjustment).into());
+        }
+
+        let calibrations = self
+            .all_calibrations()
+            .map(|calibration| {
+                Some(DatedCalibration {
+                    effective_from: calibration.effective_from().ok()?,
+                    calibration: calibration.calibration().ok()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        CalibrationHistory::new(calibrations).ok()
+    }
+}
+
+impl ConfigCalibration {
+    /// When the calibration took effect, or explain why that isn't a date we understand.
+    ///
+    /// TOML dates without a time zone are ambiguous, so we only take full dates and times with
+    /// an offset, or plain dates (which mean midnight UTC).
+    fn effective_from(&self) -> Result<Option<DateTime<Utc>>, String> {
+        let effective_from = match self.effective_from {
+            Some(ref effective_from) => effective_from.get_ref().to_string(),
+            None => return Ok(None),
+        };
+
+        if let Ok(date) = DateTime::parse_from_rfc3339(&effective_from) {
+            return Ok(Some(date.with_timezone(&Utc)));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(&effective_from, "%Y-%m-%d") {
+            return Ok(Some(Utc.from_utc_date(&date).and_hms(0, 0, 0)));
+        }
+
+        Err(format!(
+            "{} needs a time zone, like 2019-11-01T00:00:00Z, or to be just a date",
+            effective_from
+        ))
+    }
+
+    /// Build the calibration, or explain why it doesn't make sense.
+    fn calibration(&self) -> Result<Calibration, String> {
+        let gain = self.gain.as_ref().map(|gain| *gain.get_ref());
+        let offset = self.offset.as_ref().map(|offset| *offset.get_ref());
+        let points = self.points.as_ref().map(|points| points.get_ref());
+
+        let calibration = match (gain, offset, points) {
+            (None, None, Some(points)) => Calibration::table(points.clone()),
+            (_, _, Some(_)) => {
+                return Err("A calibration table can't also have a gain or an offset".into())
+            }
+            (Some(gain), offset, None) => {
+                Calibration::linear(gain, offset.unwrap_or_else(|| 0.0.into()))
+            }
+            (None, Some(offset), None) => Calibration::adjustment(offset),
+            (None, None, None) => {
+                return Err("A calibration needs a gain, an offset, or points".into())
+            }
+        };
+
+        calibration.map_err(|e| e.to_string())
+    }
+
+    /// Where the first value of the calibration is, if it has any.
+    fn start(&self) -> Option<usize> {
+        let starts = vec![
+            self.effective_from
+                .as_ref()
+                .map(|effective_from| effective_from.start()),
+            self.gain.as_ref().map(|gain| gain.start()),
+            self.offset.as_ref().map(|offset| offset.start()),
+            self.points.as_ref().map(|points| points.start()),
+        ];
+        starts.into_iter().flatten().min()
+    }
+
+    /// Make sure the calibration makes sense, and is believable.
+    fn validate(&self, contents: &str, start: usize, diagnostics: &mut Vec<Diagnostic>) {
+        if let Some(ref effective_from) = self.effective_from {
+            if let Err(message) = self.effective_from() {
+                diagnostics.push(diagnostic(contents, effective_from.start(), message));
+            }
+        }
+
+        if let Err(message) = self.calibration() {
+            diagnostics.push(diagnostic(contents, start, message));
+            return;
+        }
+
+        if let Some(ref gain) = self.gain {
+            if let Err(e) = calibration::check_gain(*gain.get_ref()) {
+                diagnostics.push(diagnostic(contents, gain.start(), e.to_string()));
+            }
+        }
+
+        if let Some(ref offset) = self.offset {
+            if let Err(e) = calibration::check_offset(*offset.get_ref()) {
+                diagnostics.push(diagnostic(contents, offset.start(), e.to_string()));
+            }
+        }
+
+        if let Some(ref points) = self.points {
+            for &(raw, actual) in points.get_ref() {
+                if let Err(e) = calibration::check_point(raw, actual) {
+                    diagnostics.push(diagnostic(contents, points.start(), e.to_string()));
+                }
+            }
+        }
+    }
+}
+
+impl ConfigPlausibleRange {
+    /// Build the plausible range, if it has any limits.
+    fn plausible_range(&self) -> Option<PlausibleRange> {
+        let min = self.min.as_ref().map(|min| *min.get_ref());
+        let max = self.max.as_ref().map(|max| *max.get_ref());
+        if min.is_none() && max.is_none() {
+            return None;
+        }
+
+        Some(PlausibleRange {
+            min,
+            max,
+            action: self.action.unwrap_or_default(),
+        })
+    }
+
+    /// Make sure the range is physically possible, and that its limits are the right way around.
+    fn validate(&self, contents: &str, start: usize, diagnostics: &mut Vec<Diagnostic>) {
+        if self.min.is_none() && self.max.is_none() {
+            diagnostics.push(diagnostic(
+                contents,
+                start,
+                "A plausible range needs a min, a max, or both".into(),
+            ));
+            return;
+        }
+
+        for limit in self.min.iter().chain(self.max.iter()) {
+            if let Err(e) = limit.get_ref().validate() {
+                diagnostics.push(diagnostic(contents, limit.start(), e.to_string()));
+            }
+        }
+
+        if let (Some(min), Some(max)) = (&self.min, &self.max) {
+            if min.get_ref().value() > max.get_ref().value() {
+                diagnostics.push(diagnostic(
+                    contents,
+                    min.start(),
+                    format!(
+                        "A plausible range from {} to {} is backwards. The min has to be below \
+                         the max",
+                        min.get_ref(),
+                        max.get_ref()
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Load a sensors.toml file
+pub fn load_sensors(path: impl AsRef<Path>) -> Result<ConfigFile, ConfigError> {
+    let path = path.as_ref();
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|e| ConfigError::Io(path.into(), e))?;
+
+    parse_sensors(&contents).map_err(|diagnostics| ConfigError::Invalid(path.into(), diagnostics))
+}
+
+/// Parse and validate the contents of a sensors.toml file.
+fn parse_sensors(contents: &str) -> Result<ConfigFile, Vec<Diagnostic>> {
+    let config: ConfigFile =
+        toml::from_str(contents).map_err(|e| vec![parse_diagnostic(contents, &e)])?;
+
+    let diagnostics = config.validate(contents);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    Ok(config)
+}
+
+/// Turn a TOML parse error into a diagnostic.
+fn parse_diagnostic(contents: &str, error: &toml::de::Error) -> Diagnostic {
+    let message = error.to_string();
+    let (line, column) = match error.line_col() {
+        Some((line, column)) => (line + 1, column + 1),
+        None => (1, 1),
+    };
+    // The message ends with the location, but we report that separately.
+    let suffix = format!(" at line {} column {}", line, column);
+    let message = message.trim_end_matches(suffix.as_str()).to_string();
+
+    // For unknown keys, TOML points at the start of the table that holds the key, which isn't
+    // very helpful in a file full of [[sensors]] tables. Point at the key itself instead.
+    if let Some(key) = unknown_field(&message) {
+        let key_line = contents
+            .lines()
+            .enumerate()
+            .skip(line - 1)
+            .find(|(_, text)| {
+                let text = text.trim_start();
+                text.starts_with(key) && text[key.len()..].trim_start().starts_with('=')
+            });
+        if let Some((index, text)) = key_line {
+            return Diagnostic {
+                line: index + 1,
+                column: text.len() - text.trim_start().len() + 1,
+                message,
+            };
+        }
+    }
+
+    Diagnostic {
+        line,
+        column,
+        message,
+    }
+}
+
+/// If the message is about an unknown field, get the name of the field.
+fn unknown_field(message: &str) -> Option<&str> {
+    let rest = message.strip_prefix("unknown field `")?;
+    let end = rest.find('`')?;
+    Some(&rest[..end])
+}
+
+/// Build a diagnostic for a problem at the specified byte offset.
+fn diagnostic(contents: &str, offset: usize, message: String) -> Diagnostic {
+    let (line, column) = line_column(contents, value_start(contents, offset));
+    Diagnostic {
+        line,
+        column,
+        message,
+    }
+}
+
+/// Find the start of the value that a span starts in. TOML's spans for floats start after the
+/// decimal point (or at the exponent), so walk back to the start of the number.
+fn value_start(contents: &str, offset: usize) -> usize {
+    let before = contents[..offset]
+        .trim_end_matches(|c: char| c.is_ascii_alphanumeric() || ['.', '+', '-', '_'].contains(&c));
+    before.len()
+}
+
+/// Find the line and column (both starting at 1) of a byte offset.
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = match before.rfind('\n') {
+        Some(newline) => before[newline + 1..].chars().count() + 1,
+        None => before.chars().count() + 1,
+    };
+    (line, column)
+}
+
+/// Watch a sensors.toml file, and apply any changes to it to the device registry.
+///
+/// The file is re-read whenever its modification time changes, or when the process receives a
+/// SIGHUP. Just like at startup, sensors.toml is only a seed, and we don't want to undo changes
+/// made through GraphQL mutations, so only what changed in the file since it was last read is
+/// written to the registry (see [`merge_reloaded`]). Sensors that were taken out of the file are
+/// removed from the registry, unless they've been changed through GraphQL since we last wrote
+/// them, in which case they're left for GraphQL to manage. If the new file can't be read or isn't
+/// valid, we complain and carry on with what we had.
+pub fn watch(
+    path: PathBuf,
+    seeds: Vec<Device>,
+    database: Arc<Database>,
+    devices: Arc<RwLock<BTreeMap<BleAddress, Device>>>,
+) -> thread::JoinHandle<()> {
+    let hangup = Arc::new(AtomicBool::new(false));
+    if let Err(e) = signal_hook::flag::register(SIGHUP, hangup.clone()) {
+        eprintln!(
+            "Could not listen for SIGHUP, so only file changes will reload sensors: {}",
+            e
+        );
+    }
+
+    thread::spawn(move || {
+        let mut previous: BTreeMap<BleAddress, Device> = seeds
+            .into_iter()
+            .map(|device| (device.address.clone(), device))
+            .collect();
+        // What we last wrote to the registry for each sensor in the file, for the sensors that
+        // haven't been changed through GraphQL since. At startup, that's the seed itself, which
+        // is what's in the registry for the sensors that were seeded.
+        let mut written = previous.clone();
+        let mut modified = modified_time(&path);
+
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+
+            let current_modified = modified_time(&path);
+            let hung_up = hangup.swap(false, Ordering::SeqCst);
+            if !hung_up && current_modified == modified {
+                continue;
+            }
+            modified = current_modified;
+
+            let config = match load_sensors(&path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!(
+                        "Could not reload sensor configuration, keeping the old one:\n{}",
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            let reloaded: BTreeMap<BleAddress, Device> = config
+                .devices()
+                .into_iter()
+                .map(|device| (device.address.clone(), device))
+                .collect();
+
+            // Write the changes to the database first, and only apply the ones that stuck. The
+            // rest still look changed next time, so they're tried again then.
+            let stored = devices.read().unwrap().clone();
+            let mut upserted = Vec::new();
+            for seed in reloaded.values() {
+                let previous_seed = previous.get(&seed.address);
+                if previous_seed == Some(seed) {
+                    continue;
+                }
+                let device = merge_reloaded(stored.get(&seed.address), previous_seed, seed);
+                // A sensor that has been changed through GraphQL stays GraphQL's to manage, even
+                // after the file changes it too.
+                let owned = !stored.contains_key(&seed.address)
+                    || stored.get(&seed.address) == written.get(&seed.address);
+                let result = database.upsert_device(
+                    &device.address,
+                    device.name.as_deref(),
+                    device.description.as_deref(),
+                    &device.calibrations,
+                    device.plausible_range.as_ref(),
+                );
+                match result {
+                    Ok(()) => upserted.push((seed.clone(), device, owned)),
+                    Err(e) => eprintln!(
+                        "Could not store the reloaded sensor {} in the database: {}",
+                        device.address, e
+                    ),
+                }
+            }
+            let mut removed = Vec::new();
+            let mut released = Vec::new();
+            for address in previous.keys() {
+                if reloaded.contains_key(address) {
+                    continue;
+                }
+                if stored.get(address) != written.get(address) {
+                    released.push(address.clone());
+                    continue;
+                }
+                match database.delete_device(address) {
+                    Ok(_) => removed.push(address.clone()),
+                    Err(e) => eprintln!(
+                        "Could not remove the sensor {} from the database: {}",
+                        address, e
+                    ),
+                }
+            }
+
+            // Readers see either all of the reloaded sensors, or none of them.
+            let mut registry = devices.write().unwrap();
+            let mut replacement = registry.clone();
+            for (seed, device, owned) in upserted {
+                println!("Reloaded sensor {}", device.address);
+                replacement.insert(device.address.clone(), device.clone());
+                previous.insert(seed.address.clone(), seed);
+                if owned {
+                    written.insert(device.address.clone(), device);
+                } else {
+                    written.remove(&device.address);
+                }
+            }
+            for address in &removed {
+                println!("Removed sensor {}", address);
+                replacement.remove(address);
+                previous.remove(address);
+                written.remove(address);
+            }
+            for address in &released {
+                println!(
+                    "Kept sensor {}, which was taken out of the file but has been changed through \
+                     GraphQL",
+                    address
+                );
+                previous.remove(address);
+                written.remove(address);
+            }
+            *registry = replacement;
+        }
+    })
+}
+
+/// Work out what a sensor that changed in the file should look like in the registry, given what's
+/// stored for it now and what the file said about it last time.
+///
+/// Only the fields that changed in the file are taken from it, so anything else that was changed
+/// through GraphQL is kept. Calibrations are added to the stored calibration history rather than
+/// replacing it, the same way `upsertDevice` adds them: one without a date takes effect now,
+/// unless the device has never been calibrated.
+fn merge_reloaded(stored: Option<&Device>, previous: Option<&Device>, seed: &Device) -> Device {
+    let stored = match stored {
+        Some(stored) => stored,
+        None => return seed.clone(),
+    };
+    let mut device = stored.clone();
+    if previous.map(|previous| &previous.name) != Some(&seed.name) {
+        device.name = seed.name.clone();
+    }
+    if previous.map(|previous| &previous.description) != Some(&seed.description) {
+        device.description = seed.description.clone();
+    }
+    if previous.map(|previous| &previous.plausible_range) != Some(&seed.plausible_range) {
+        device.plausible_range = seed.plausible_range;
+    }
+
+    let previous_calibrations = previous.map(|previous| previous.calibrations.entries());
+    for calibration in seed.calibrations.entries() {
+        if previous_calibrations.is_some_and(|entries| entries.contains(calibration)) {
+            continue;
+        }
+        let effective_from = match calibration.effective_from {
+            Some(effective_from) => Some(effective_from),
+            None if device.calibrations.entries().is_empty() => None,
+            // Measurements are only stored to the second, so calibrations start on one.
+            None => Some(Utc::now().trunc_subsecs(0)),
+        };
+        device.calibrations.insert(DatedCalibration {
+            effective_from,
+            calibration: calibration.calibration.clone(),
+        });
+    }
+
+    device
+}
+
+/// When the file was last modified, if we can tell.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed(name: &str, adjustment: f64) -> Device {
+        Device {
+            address: "f4d55889b1d6".parse().unwrap(),
+            name: Some(name.into()),
+            description: None,
+            calibrations: Calibration::Offset(adjustment.into()).into(),
+            plausible_range: None,
+        }
+    }
+
+    #[test]
+    fn reloading_keeps_graphql_changes() {
+        let previous = seed("Basement", -1.0);
+
+        // Recalibrated, and given a plausible range, through GraphQL.
+        let mut stored = previous.clone();
+        stored.calibrations.insert(DatedCalibration {
+            effective_from: Some(Utc.timestamp_opt(1_572_609_600, 0).unwrap()),
+            calibration: Calibration::Offset((-2.0).into()),
+        });
+        stored.plausible_range = Some(PlausibleRange {
+            min: Some(Celsius::from(-10.0)),
+            max: None,
+            action: OutOfRangeAction::Flag,
+        });
+
+        // Only the name changed in the file.
+        let merged = merge_reloaded(Some(&stored), Some(&previous), &seed("Cellar", -1.0));
+        assert_eq!(merged.name.as_deref(), Some("Cellar"));
+        assert_eq!(merged.calibrations, stored.calibrations);
+        assert_eq!(merged.plausible_range, stored.plausible_range);
+    }
+
+    #[test]
+    fn reloaded_calibrations_are_added_to_the_history() {
+        let previous = seed("Basement", -1.0);
+        let merged = merge_reloaded(Some(&previous), Some(&previous), &seed("Basement", -3.0));
+
+        let entries = merged.calibrations.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], previous.calibrations.entries()[0]);
+        assert!(entries[1].effective_from.is_some());
+        assert_eq!(
+            merged.calibrations.current(),
+            Some(&Calibration::Offset((-3.0).into()))
+        );
+    }
+
+    #[test]
+    fn new_sensors_are_seeded_as_they_are() {
+        let seed = seed("Basement", -1.0);
+        assert!(merge_reloaded(None, None, &seed) == seed);
+    }
+
+    /// The problems with a file, as (line, column, message).
+    fn problems(contents: &str) -> Vec<(usize, usize, String)> {
+        match parse_sensors(contents) {
+            Ok(_) => Vec::new(),
+            Err(diagnostics) => diagnostics
+                .into_iter()
+                .map(|diagnostic| (diagnostic.line, diagnostic.column, diagnostic.message))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn valid_file() {
+        let config = parse_sensors(
+            "[[sensors]]\n\
+             address = \"d0f7083ca3b1\"\n\
+             name = \"Upstairs\"\n\
+             adjustment = -1.5\n",
+        )
+        .unwrap();
+
+        let devices = config.devices();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].address.to_string(), "d0f7083ca3b1");
+        assert_eq!(devices[0].name.as_deref(), Some("Upstairs"));
+        assert_eq!(
+            devices[0].calibrations.current(),
+            Some(&Calibration::Offset((-1.5).into()))
+        );
+    }
+
+    #[test]
+    fn duplicate_address() {
+        let problems = problems(
+            "[[sensors]]\n\
+             address = \"d0f7083ca3b1\"\n\
+             \n\
+             [[sensors]]\n\
+             address = \"D0:F7:08:3C:A3:B1\"\n",
+        );
+
+        assert_eq!(problems.len(), 1);
+        let (line, column, ref message) = problems[0];
+        assert_eq!((line, column), (5, 11));
+        assert!(
+            message.contains("more than once (first at line 2)"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn bad_address() {
+        let problems = problems(
+            "[[sensors]]\n\
+             name = \"Upstairs\"\n\
+             address = \"d0f7083ca3bz\"\n",
+        );
+
+        assert_eq!(problems.len(), 1);
+        let (line, column, ref message) = problems[0];
+        assert_eq!((line, column), (3, 11));
+        assert!(
+            message.starts_with("\"d0f7083ca3bz\" is not a BLE address"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn absurd_adjustment() {
+        let problems = problems(
+            "[[sensors]]\n\
+             address = \"d0f7083ca3b1\"\n\
+             adjustment = 40.0\n",
+        );
+
+        assert_eq!(problems.len(), 1);
+        let (line, column, ref message) = problems[0];
+        assert_eq!((line, column), (3, 14));
+        assert!(message.contains("is not believable"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_key() {
+        let problems = problems(
+            "[[sensors]]\n\
+             address = \"d0f7083ca3b1\"\n\
+             \n\
+             [[sensors]]\n\
+             address = \"f4d55889b1d6\"\n\
+             \x20   adjustmnet = 1.0\n",
+        );
+
+        assert_eq!(problems.len(), 1);
+        let (line, column, ref message) = problems[0];
+        assert_eq!((line, column), (6, 5));
+        assert!(
+            message.starts_with("unknown field `adjustmnet`"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let problems = problems(
+            "[[sensors]]\n\
+             address = \"nope\"\n\
+             adjustment = 100.0\n",
+        );
+
+        let locations: Vec<(usize, usize)> = problems
+            .iter()
+            .map(|&(line, column, _)| (line, column))
+            .collect();
+        assert_eq!(locations, vec![(2, 11), (3, 14)]);
+    }
+
+    #[test]
+    fn unknown_field_names() {
+        assert_eq!(
+            unknown_field("unknown field `adjustmnet`, expected one of `address`, `name`"),
+            Some("adjustmnet")
+        );
+        assert_eq!(unknown_field("missing field `address`"), None);
+    }
+}
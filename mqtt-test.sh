@@ -0,0 +1,29 @@
+#!/bin/sh
+# Publish a measurement to the Mosquitto broker from docker-compose, and check that the server
+# stored it. Run it with everything up:
+#
+#     docker-compose up -d
+#     ./mqtt-test.sh
+set -eu
+
+ENDPOINT="${ENDPOINT:-http://localhost:8080/graphql}"
+ADDRESS="${ADDRESS:-f4d55889b1d6}"
+
+# A timestamp of our own tells this measurement apart from any that were already there.
+now="$(date -u +%Y-%m-%dT%H:%M:%S)"
+docker-compose exec -T mosquitto mosquitto_pub \
+  -t "sensors/${ADDRESS}/temperature" \
+  -m "{\"temp_c\": 21.5, \"timestamp\": \"${now}Z\"}"
+
+query="{\"query\": \"{ device(address: \\\"${ADDRESS}\\\") { measurements(count: 10) { date } } }\"}"
+for _ in $(seq 1 30); do
+  if curl -fsS -H 'content-type: application/json' -d "$query" "$ENDPOINT" \
+    | grep -q "\"${now}+00:00\""; then
+    echo "The measurement published at ${now}Z was stored"
+    exit 0
+  fi
+  sleep 1
+done
+
+echo "The measurement published at ${now}Z was not stored within 30 seconds" >&2
+exit 1
@@ -0,0 +1,142 @@
+//! A newtype wrapper for BLE addresses, so that the same device always has the same address.
+//!
+//! BLE addresses get written down in a number of different ways. Our sensors.toml uses
+//! `d0f7083ca3b1`, but most BLE tooling (and so most collectors) would send `D0:F7:08:3C:A3:B1`.
+//! Those are the same device, so we parse all of the common forms and always store the canonical
+//! one: twelve lowercase hexadecimal digits with no separators.
+//!
+//! ```
+//! # use temperature_app::address::BleAddress;
+//! let bare: BleAddress = "d0f7083ca3b1".parse().unwrap();
+//! let colons: BleAddress = "D0:F7:08:3C:A3:B1".parse().unwrap();
+//! let dashes: BleAddress = "d0-f7-08-3c-a3-b1".parse().unwrap();
+//! assert_eq!(bare, colons);
+//! assert_eq!(bare, dashes);
+//! assert_eq!(colons.to_string(), "d0f7083ca3b1");
+//! ```
+//!
+//! ```
+//! # use temperature_app::address::BleAddress;
+//! assert!("d0f7083ca3b".parse::<BleAddress>().is_err());
+//! assert!("d0:f7:08:3c:a3b1".parse::<BleAddress>().is_err());
+//! assert!("g0f7083ca3b1".parse::<BleAddress>().is_err());
+//! ```
+
+use juniper::{ParseScalarResult, ParseScalarValue, Value};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The BLE (MAC) address of a device, in canonical form
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BleAddress(String);
+
+/// Errors that can occur when parsing a BLE address.
+#[derive(Debug)]
+pub enum BleAddressError {
+    /// The address didn't have exactly twelve hexadecimal digits.
+    InvalidLength,
+    /// The address contained something other than hexadecimal digits and separators.
+    InvalidCharacter(char),
+    /// The address used separators, but not between every pair of digits, or it mixed colons
+    /// and dashes.
+    InvalidSeparators,
+}
+
+impl std::fmt::Display for BleAddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            BleAddressError::InvalidLength => {
+                "A BLE address must have exactly 12 hexadecimal digits".fmt(f)
+            }
+            BleAddressError::InvalidCharacter(c) => {
+                write!(f, "A BLE address can't contain the character {:?}", c)
+            }
+            BleAddressError::InvalidSeparators => {
+                "A BLE address must separate every pair of digits with the same separator".fmt(f)
+            }
+        }
+    }
+}
+
+impl BleAddress {
+    /// Get the canonical string form of the address back.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for BleAddress {
+    type Err = BleAddressError;
+
+    /// Parse an address written bare (`d0f7083ca3b1`), or with colons (`d0:f7:08:3c:a3:b1`) or
+    /// dashes (`d0-f7-08-3c-a3-b1`) between the pairs, in either upper or lower case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some(c) = s
+            .chars()
+            .find(|c| !c.is_ascii_hexdigit() && *c != ':' && *c != '-')
+        {
+            return Err(BleAddressError::InvalidCharacter(c));
+        }
+
+        let digits: String = if s.contains(':') || s.contains('-') {
+            let separator = if s.contains(':') { ':' } else { '-' };
+            let pairs: Vec<&str> = s.split(separator).collect();
+            if pairs.len() != 6 || pairs.iter().any(|pair| pair.len() != 2) {
+                return Err(BleAddressError::InvalidSeparators);
+            }
+            pairs.concat()
+        } else {
+            s.into()
+        };
+
+        if digits.len() != 12 {
+            return Err(BleAddressError::InvalidLength);
+        }
+
+        Ok(BleAddress(digits.to_ascii_lowercase()))
+    }
+}
+
+impl std::fmt::Display for BleAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+impl AsRef<str> for BleAddress {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Serialize for BleAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for BleAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+juniper::graphql_scalar!(BleAddress {
+    description: "The BLE address of a device. Accepts 12 hexadecimal digits, optionally \
+                  separated by colons or dashes, in either case. Always returned as 12 lowercase \
+                  hexadecimal digits."
+
+    resolve(&self) -> Value {
+        Value::scalar(self.0.clone())
+    }
+
+    from_input_value(v: &InputValue) -> Option<BleAddress> {
+        v.as_scalar_value::<String>().and_then(|s| s.parse().ok())
+    }
+
+    from_str<'a>(value: ScalarToken<'a>) -> ParseScalarResult<'a> {
+        <String as ParseScalarValue>::from_str(value)
+    }
+});
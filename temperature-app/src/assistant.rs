@@ -0,0 +1,365 @@
+//! The calibration assistant, which works out how to calibrate a sensor by comparing it to a
+//! sensor that we trust.
+//!
+//! Put the two sensors next to each other for a while, then ask the assistant to fit a
+//! calibration over that time. It pairs every raw reading of the sensor being calibrated with the
+//! reference reading closest in time, and fits an offset (or a gain and an offset) by least
+//! squares, so that the calibrated readings are as close to the reference readings as possible.
+//!
+//! ```
+//! # use temperature_app::assistant::fit;
+//! # use temperature_app::calibration::Calibration;
+//! // A sensor that reads six degrees high.
+//! let pairs = vec![
+//!     (26.0.into(), 20.0.into()),
+//!     (27.0.into(), 21.0.into()),
+//!     (28.0.into(), 22.0.into()),
+//! ];
+//! let fit = fit(&pairs, false).unwrap();
+//! assert_eq!(fit.calibration, Calibration::Offset((-6.0).into()));
+//! assert_eq!(fit.rms_error.value(), 0.0);
+//! ```
+//!
+//! ```
+//! # use temperature_app::assistant::fit;
+//! # use temperature_app::calibration::Calibration;
+//! // A sensor that exaggerates: actual = raw * 0.5 + 10.
+//! let pairs = vec![
+//!     (10.0.into(), 15.0.into()),
+//!     (20.0.into(), 20.0.into()),
+//!     (30.0.into(), 25.0.into()),
+//! ];
+//! let fit = fit(&pairs, true).unwrap();
+//! match fit.calibration {
+//!     Calibration::Linear { gain, offset } => {
+//!         assert!((gain - 0.5).abs() < 1e-9);
+//!         assert!((offset.value() - 10.0).abs() < 1e-9);
+//!     }
+//!     _ => panic!("Expected a linear calibration"),
+//! }
+//! assert!((fit.r_squared.unwrap() - 1.0).abs() < 1e-9);
+//! ```
+//!
+//! Calibrations that are too far off to believe aren't fitted at all.
+//!
+//! ```
+//! # use temperature_app::assistant::{fit, AssistantError};
+//! // A sensor that reads forty degrees high.
+//! let pairs = vec![
+//!     (60.0.into(), 20.0.into()),
+//!     (61.0.into(), 21.0.into()),
+//!     (62.0.into(), 22.0.into()),
+//! ];
+//! match fit(&pairs, false) {
+//!     Err(AssistantError::Calibration(_)) => {}
+//!     _ => panic!("Expected an unbelievable calibration"),
+//! }
+//!
+//! // A gain of 4 is as unbelievable as an offset of 40.
+//! let pairs = vec![
+//!     (19.0.into(), 16.0.into()),
+//!     (20.0.into(), 20.0.into()),
+//!     (21.0.into(), 24.0.into()),
+//! ];
+//! match fit(&pairs, true) {
+//!     Err(AssistantError::Calibration(_)) => {}
+//!     _ => panic!("Expected an unbelievable calibration"),
+//! }
+//! ```
+
+use crate::{
+    address::BleAddress,
+    calibration::{Calibration, CalibrationError, CalibrationHistory},
+    database::{Database, DatabaseError},
+    temperature::{Celsius, CelsiusDelta},
+};
+use chrono::{DateTime, Duration, Utc};
+
+/// The fewest pairs of readings that we're willing to fit a calibration to.
+pub const MIN_PAIRS: usize = 3;
+
+/// How far apart in time two readings can be and still be paired up, unless told otherwise.
+pub const DEFAULT_TOLERANCE_SECONDS: i64 = 300;
+
+/// How many readings of a sensor to read from the database at a time.
+const PAGE_SIZE: u32 = 10_000;
+
+/// Errors that can occur when fitting a calibration.
+#[derive(Debug)]
+pub enum AssistantError {
+    /// The readings couldn't be loaded.
+    Database(DatabaseError),
+    /// There weren't enough readings close enough in time to fit anything.
+    NotEnoughPairs(usize),
+    /// All of the readings were the same, so there's no way to tell what the gain should be.
+    NoVariation,
+    /// The fitted calibration doesn't make sense.
+    Calibration(CalibrationError),
+}
+
+impl std::fmt::Display for AssistantError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            AssistantError::Database(e) => e.fmt(f),
+            AssistantError::NotEnoughPairs(pairs) => write!(
+                f,
+                "Only found {} pairs of readings close enough in time, but need at least {}",
+                pairs, MIN_PAIRS
+            ),
+            AssistantError::NoVariation => {
+                "The readings don't vary, so there's no way to fit a gain".fmt(f)
+            }
+            AssistantError::Calibration(e) => write!(f, "The fitted calibration is invalid: {}", e),
+        }
+    }
+}
+
+impl From<DatabaseError> for AssistantError {
+    fn from(e: DatabaseError) -> Self {
+        AssistantError::Database(e)
+    }
+}
+
+/// A fitted calibration, and how well it fits.
+#[derive(Debug, Clone)]
+pub struct Fit {
+    /// The calibration that brings the readings closest to the reference readings
+    pub calibration: Calibration,
+    /// How many pairs of readings the calibration was fitted to
+    pub pairs: usize,
+    /// The root-mean-square difference between the calibrated and reference readings, in degrees
+    /// celsius
+    pub rms_error: CelsiusDelta,
+    /// The largest difference between a calibrated and a reference reading, in degrees celsius
+    pub max_error: CelsiusDelta,
+    /// How much of the variation in the reference readings the calibration explains, from 0 to 1.
+    /// Missing if the reference readings don't vary at all.
+    pub r_squared: Option<f64>,
+}
+
+impl Fit {
+    /// Describe the fit as a sensor for sensors.toml.
+    pub fn sensors_toml(&self, address: &BleAddress, reference: &BleAddress) -> String {
+        let mut toml = format!(
+            "[[sensors]]\n\
+             address = \"{}\"\n\
+             # Fitted against {} over {} pairs of readings, with an RMS error of {:.3} degrees\n",
+            address,
+            reference,
+            self.pairs,
+            self.rms_error.value()
+        );
+        match self.calibration {
+            Calibration::Offset(offset) => {
+                toml.push_str(&format!("adjustment = {:.2}\n", offset.value()));
+            }
+            Calibration::Linear { gain, offset } => {
+                toml.push_str(&format!(
+                    "[sensors.calibration]\ngain = {:.4}\noffset = {:.2}\n",
+                    gain,
+                    offset.value()
+                ));
+            }
+            // We never fit tables, but there's no harm in describing one.
+            Calibration::Table(ref points) => {
+                let points: Vec<String> = points
+                    .iter()
+                    .map(|(raw, actual)| format!("[{:.2}, {:.2}]", raw.value(), actual.value()))
+                    .collect();
+                toml.push_str(&format!(
+                    "[sensors.calibration]\npoints = [{}]\n",
+                    points.join(", ")
+                ));
+            }
+        }
+        toml
+    }
+}
+
+/// Pair up readings with the reference readings closest to them in time.
+///
+/// Both lists have to be sorted by date. Readings with no reference reading within `tolerance`
+/// are left out. Each pair is (raw reading, reference reading).
+///
+/// ```
+/// # use temperature_app::assistant::align;
+/// # use chrono::{Duration, TimeZone, Utc};
+/// let at = |minute| Utc.ymd(2019, 11, 1).and_hms(12, minute, 0);
+/// let readings = vec![(at(0), 26.0.into()), (at(10), 27.0.into()), (at(30), 28.0.into())];
+/// let reference = vec![(at(1), 20.0.into()), (at(8), 21.0.into()), (at(11), 21.5.into())];
+///
+/// let pairs = align(&readings, &reference, Duration::minutes(5));
+/// assert_eq!(pairs.len(), 2);
+/// assert_eq!(pairs[1].1.value(), 21.5);
+/// ```
+pub fn align(
+    readings: &[(DateTime<Utc>, Celsius)],
+    reference: &[(DateTime<Utc>, Celsius)],
+    tolerance: Duration,
+) -> Vec<(Celsius, Celsius)> {
+    let mut pairs = Vec::new();
+    let mut next = 0;
+
+    for &(date, raw) in readings {
+        // Move past the reference readings that are before this one, keeping the last of them
+        // around, since it might still be the closest.
+        while next < reference.len() && reference[next].0 < date {
+            next += 1;
+        }
+
+        let before = next.checked_sub(1).map(|index| reference[index]);
+        let after = reference.get(next).cloned();
+        let closest = match (before, after) {
+            (Some(before), Some(after)) => {
+                if date - before.0 <= after.0 - date {
+                    Some(before)
+                } else {
+                    Some(after)
+                }
+            }
+            (before, after) => before.or(after),
+        };
+
+        if let Some((reference_date, actual)) = closest {
+            let distance = if reference_date > date {
+                reference_date - date
+            } else {
+                date - reference_date
+            };
+            if distance <= tolerance {
+                pairs.push((raw, actual));
+            }
+        }
+    }
+
+    pairs
+}
+
+/// Fit a calibration to (raw, actual) pairs by least squares: an offset, or if `fit_gain` is
+/// set, a gain and an offset. A calibration that couldn't be stored because it isn't believable
+/// is an error too.
+pub fn fit(pairs: &[(Celsius, Celsius)], fit_gain: bool) -> Result<Fit, AssistantError> {
+    if pairs.len() < MIN_PAIRS {
+        return Err(AssistantError::NotEnoughPairs(pairs.len()));
+    }
+
+    let count = pairs.len() as f64;
+    let mean_raw = pairs.iter().map(|(raw, _)| raw.value()).sum::<f64>() / count;
+    let mean_actual = pairs.iter().map(|(_, actual)| actual.value()).sum::<f64>() / count;
+
+    let calibration = if fit_gain {
+        let covariance: f64 = pairs
+            .iter()
+            .map(|(raw, actual)| (raw.value() - mean_raw) * (actual.value() - mean_actual))
+            .sum();
+        let variance: f64 = pairs
+            .iter()
+            .map(|(raw, _)| (raw.value() - mean_raw).powi(2))
+            .sum();
+        if variance == 0.0 {
+            return Err(AssistantError::NoVariation);
+        }
+
+        let gain = covariance / variance;
+        Calibration::linear(gain, (mean_actual - gain * mean_raw).into())
+            .map_err(AssistantError::Calibration)?
+    } else {
+        Calibration::Offset((mean_actual - mean_raw).into())
+    };
+    // A sensor this far off is broken, or wasn't next to the reference.
+    calibration
+        .check_believable()
+        .map_err(AssistantError::Calibration)?;
+
+    let errors: Vec<f64> = pairs
+        .iter()
+        .map(|&(raw, actual)| calibration.apply(raw).value() - actual.value())
+        .collect();
+    let squared_errors: f64 = errors.iter().map(|error| error.powi(2)).sum();
+    let total: f64 = pairs
+        .iter()
+        .map(|(_, actual)| (actual.value() - mean_actual).powi(2))
+        .sum();
+
+    Ok(Fit {
+        calibration,
+        pairs: pairs.len(),
+        rms_error: (squared_errors / count).sqrt().into(),
+        max_error: errors
+            .iter()
+            .fold(0.0, |max, error| error.abs().max(max))
+            .into(),
+        r_squared: if total > 0.0 {
+            Some(1.0 - squared_errors / total)
+        } else {
+            None
+        },
+    })
+}
+
+/// What to fit a calibration to
+#[derive(Debug, Clone)]
+pub struct FitOptions {
+    /// Only use readings taken at or after this date
+    pub from: DateTime<Utc>,
+    /// Only use readings taken at or before this date
+    pub until: DateTime<Utc>,
+    /// How far apart in time two readings can be and still be paired up
+    pub tolerance: Duration,
+    /// Whether to fit a gain as well as an offset
+    pub fit_gain: bool,
+}
+
+/// Fit a calibration for a device against a reference device.
+///
+/// The device's raw readings are used, but the reference's readings are calibrated with its own
+/// calibration history first, since that's what makes it trustworthy.
+pub fn fit_against_reference(
+    database: &Database,
+    address: &BleAddress,
+    reference: &BleAddress,
+    reference_calibrations: &CalibrationHistory,
+    options: &FitOptions,
+) -> Result<Fit, AssistantError> {
+    let readings = readings_between(database, address, options.from, options.until)?;
+    let reference_readings: Vec<(DateTime<Utc>, Celsius)> =
+        readings_between(database, reference, options.from, options.until)?
+            .into_iter()
+            .map(|(date, raw)| (date, reference_calibrations.apply(raw, date)))
+            .collect();
+
+    fit(
+        &align(&readings, &reference_readings, options.tolerance),
+        options.fit_gain,
+    )
+}
+
+/// The raw readings of a device between two dates, oldest first. They're read a page at a time,
+/// the same way that [`Export`](crate::export::Export) does, so long stretches aren't cut short.
+fn readings_between(
+    database: &Database,
+    address: &BleAddress,
+    from: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Result<Vec<(DateTime<Utc>, Celsius)>, DatabaseError> {
+    let mut readings = Vec::new();
+    let mut next = from;
+    loop {
+        let measurements = database.select_measurements_between(address, next, until, PAGE_SIZE)?;
+        let full = measurements.len() >= PAGE_SIZE as usize;
+        let last = measurements.last().and_then(|measurement| measurement.date);
+        readings.extend(measurements.into_iter().filter_map(|measurement| {
+            match (measurement.date, measurement.temperature) {
+                (Some(date), Some(temperature)) => Some((date, temperature)),
+                _ => None,
+            }
+        }));
+
+        // Dates only go down to the second, and a device has at most one measurement each second,
+        // so the next page starts a second after the last measurement of this one.
+        match last {
+            Some(last) if full => next = last + Duration::seconds(1),
+            _ => return Ok(readings),
+        }
+    }
+}
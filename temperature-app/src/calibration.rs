@@ -0,0 +1,391 @@
+//! Calibrations, which turn a sensor's raw readings into what the temperature actually was.
+//!
+//! The simplest calibration is a fixed offset (the `adjustment` in sensors.toml), but some
+//! sensors are off by different amounts at different temperatures. For those, there's a linear
+//! calibration (a gain and an offset), or a table of (raw, actual) points that we interpolate
+//! between.
+//!
+//! ```
+//! # use temperature_app::calibration::Calibration;
+//! let offset = Calibration::Offset((-6.16).into());
+//! assert!((offset.apply(26.16.into()).value() - 20.0).abs() < 1e-9);
+//!
+//! let linear = Calibration::linear(1.1, (-2.0).into()).unwrap();
+//! assert!((linear.apply(20.0.into()).value() - 20.0).abs() < 1e-9);
+//!
+//! let table = Calibration::table(vec![
+//!     (10.0.into(), 8.0.into()),
+//!     (20.0.into(), 14.0.into()),
+//!     (30.0.into(), 22.0.into()),
+//! ])
+//! .unwrap();
+//! // Between points, we interpolate...
+//! assert!((table.apply(25.0.into()).value() - 18.0).abs() < 1e-9);
+//! // ...and outside of them, we extend the nearest segment.
+//! assert!((table.apply(0.0.into()).value() - 2.0).abs() < 1e-9);
+//! ```
+//!
+//! ```
+//! # use temperature_app::calibration::Calibration;
+//! // A table needs at least two points, and can't list the same raw value twice.
+//! assert!(Calibration::table(vec![(10.0.into(), 8.0.into())]).is_err());
+//! assert!(Calibration::table(vec![(10.0.into(), 8.0.into()), (10.0.into(), 9.0.into())]).is_err());
+//!
+//! // Calibrations that make sense can still be too far off for anybody to have meant them.
+//! assert!(Calibration::adjustment((-1.5).into()).unwrap().check_believable().is_ok());
+//! assert!(Calibration::adjustment(68.0.into()).unwrap().check_believable().is_err());
+//! assert!(Calibration::linear(10.0, 0.0.into()).unwrap().check_believable().is_err());
+//! ```
+//!
+//! Calibrations are applied when measurements are read, not when they're stored, so changing a
+//! device's calibration would change what all of its old measurements mean. To recalibrate a
+//! sensor without rewriting history, a device keeps a [`CalibrationHistory`]: every calibration
+//! has the date it took effect, and each measurement uses the calibration that was in effect when
+//! it was taken.
+//!
+//! ```
+//! # use temperature_app::calibration::{Calibration, CalibrationHistory, DatedCalibration};
+//! # use chrono::{TimeZone, Utc};
+//! let history = CalibrationHistory::new(vec![
+//!     DatedCalibration {
+//!         effective_from: None,
+//!         calibration: Calibration::Offset((-6.0).into()),
+//!     },
+//!     DatedCalibration {
+//!         effective_from: Some(Utc.ymd(2019, 11, 1).and_hms(0, 0, 0)),
+//!         calibration: Calibration::Offset((-1.0).into()),
+//!     },
+//! ])
+//! .unwrap();
+//!
+//! let before = Utc.ymd(2019, 10, 31).and_hms(12, 0, 0);
+//! let after = Utc.ymd(2019, 11, 2).and_hms(12, 0, 0);
+//! assert_eq!(history.apply(26.0.into(), before).value(), 20.0);
+//! assert_eq!(history.apply(26.0.into(), after).value(), 25.0);
+//!
+//! // Calibrations that take effect in the future aren't current yet.
+//! let mut history = history;
+//! history.insert(DatedCalibration {
+//!     effective_from: Some(Utc.ymd(2999, 1, 1).and_hms(0, 0, 0)),
+//!     calibration: Calibration::Offset((-2.0).into()),
+//! });
+//! assert_eq!(history.current(), Some(&Calibration::Offset((-1.0).into())));
+//! ```
+
+use crate::temperature::{Celsius, CelsiusDelta};
+use chrono::{DateTime, Utc};
+
+/// The largest offset that we believe somebody meant to make, in degrees celsius. Anything bigger
+/// than this is almost certainly a typo (or a value in the wrong unit).
+pub const MAX_OFFSET: f64 = 25.0;
+
+/// The smallest gain that we believe somebody meant to use. A sensor that's off by more than a
+/// factor of two is broken, not miscalibrated.
+pub const MIN_GAIN: f64 = 0.5;
+
+/// The largest gain that we believe somebody meant to use.
+pub const MAX_GAIN: f64 = 2.0;
+
+/// How to turn a sensor's raw readings into actual temperatures
+#[derive(Debug, Clone, PartialEq)]
+pub enum Calibration {
+    /// Add a fixed number of degrees to every reading.
+    Offset(CelsiusDelta),
+    /// Multiply every reading by `gain`, then add `offset`.
+    Linear {
+        /// How much to scale the raw reading by
+        gain: f64,
+        /// How much to add after scaling
+        offset: CelsiusDelta,
+    },
+    /// Interpolate between known (raw, actual) pairs, sorted by the raw reading. Readings outside
+    /// of the table extend the first or last segment.
+    Table(Vec<(Celsius, Celsius)>),
+}
+
+/// Errors that can occur when building a calibration.
+#[derive(Debug)]
+pub enum CalibrationError {
+    /// One of the numbers was NaN or infinite.
+    NotFinite,
+    /// A linear calibration's gain was zero or negative.
+    InvalidGain,
+    /// A calibration table had fewer than two points.
+    TooFewPoints,
+    /// A calibration table listed the same raw reading more than once.
+    DuplicatePoint(f64),
+    /// A calibration history had more than one calibration that took effect at the same time.
+    DuplicateEffectiveFrom(Option<DateTime<Utc>>),
+    /// An offset was bigger than [`MAX_OFFSET`].
+    UnbelievableOffset(CelsiusDelta),
+    /// A gain was outside of [`MIN_GAIN`] and [`MAX_GAIN`].
+    UnbelievableGain(f64),
+    /// A point in a calibration table was further than [`MAX_OFFSET`] from what the sensor read.
+    UnbelievablePoint(Celsius, Celsius),
+}
+
+impl std::fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            CalibrationError::NotFinite => "Calibration values must be finite numbers".fmt(f),
+            CalibrationError::InvalidGain => "A calibration's gain must be positive".fmt(f),
+            CalibrationError::TooFewPoints => {
+                "A calibration table needs at least two points".fmt(f)
+            }
+            CalibrationError::DuplicatePoint(raw) => write!(
+                f,
+                "The calibration table lists the raw reading {} more than once",
+                raw
+            ),
+            CalibrationError::DuplicateEffectiveFrom(Some(date)) => write!(
+                f,
+                "More than one calibration takes effect at {}",
+                date.to_rfc3339()
+            ),
+            CalibrationError::DuplicateEffectiveFrom(None) => {
+                "Only one calibration can be in effect from the beginning".fmt(f)
+            }
+            CalibrationError::UnbelievableOffset(offset) => write!(
+                f,
+                "An adjustment of {:.2} is not believable. Adjustments and offsets must be \
+                 between -{}°C and {}°C",
+                offset, MAX_OFFSET, MAX_OFFSET
+            ),
+            CalibrationError::UnbelievableGain(gain) => write!(
+                f,
+                "A gain of {} is not believable. Gains must be between {} and {}",
+                gain, MIN_GAIN, MAX_GAIN
+            ),
+            CalibrationError::UnbelievablePoint(raw, actual) => write!(
+                f,
+                "A reading of {} that was actually {} is not believable. Points must be within \
+                 {} degrees of each other",
+                raw, actual, MAX_OFFSET
+            ),
+        }
+    }
+}
+
+impl Calibration {
+    /// A calibration that leaves readings alone.
+    pub fn none() -> Self {
+        Calibration::Offset(0.0.into())
+    }
+
+    /// Build a calibration that adds a fixed offset, making sure that it makes sense.
+    pub fn adjustment(offset: CelsiusDelta) -> Result<Self, CalibrationError> {
+        if !offset.value().is_finite() {
+            return Err(CalibrationError::NotFinite);
+        }
+
+        Ok(Calibration::Offset(offset))
+    }
+
+    /// Build a linear calibration, making sure that it makes sense.
+    pub fn linear(gain: f64, offset: CelsiusDelta) -> Result<Self, CalibrationError> {
+        if !gain.is_finite() || !offset.value().is_finite() {
+            return Err(CalibrationError::NotFinite);
+        }
+        if gain <= 0.0 {
+            return Err(CalibrationError::InvalidGain);
+        }
+
+        Ok(Calibration::Linear { gain, offset })
+    }
+
+    /// Build a calibration table from (raw, actual) points, in any order, making sure that it
+    /// makes sense.
+    pub fn table(mut points: Vec<(Celsius, Celsius)>) -> Result<Self, CalibrationError> {
+        if points
+            .iter()
+            .any(|(raw, actual)| !raw.value().is_finite() || !actual.value().is_finite())
+        {
+            return Err(CalibrationError::NotFinite);
+        }
+        if points.len() < 2 {
+            return Err(CalibrationError::TooFewPoints);
+        }
+
+        // Everything is finite, so this comparison can't fail.
+        points.sort_by(|a, b| a.0.value().partial_cmp(&b.0.value()).unwrap());
+        if let Some(pair) = points.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(CalibrationError::DuplicatePoint(pair[0].0.value()));
+        }
+
+        Ok(Calibration::Table(points))
+    }
+
+    /// Make sure that the calibration is one that somebody meant to make: that its offset, gain,
+    /// and points are all within [`check_offset`], [`check_gain`], and [`check_point`].
+    pub fn check_believable(&self) -> Result<(), CalibrationError> {
+        match self {
+            Calibration::Offset(offset) => check_offset(*offset),
+            Calibration::Linear { gain, offset } => {
+                check_gain(*gain)?;
+                check_offset(*offset)
+            }
+            Calibration::Table(points) => points
+                .iter()
+                .try_for_each(|&(raw, actual)| check_point(raw, actual)),
+        }
+    }
+
+    /// Turn a raw reading into the actual temperature.
+    pub fn apply(&self, raw: Celsius) -> Celsius {
+        match self {
+            Calibration::Offset(offset) => raw + *offset,
+            Calibration::Linear { gain, offset } => (raw.value() * gain + offset.value()).into(),
+            // Tables built through `Calibration::table` always have at least two points, but don't
+            // fall over if somebody built a smaller one by hand.
+            Calibration::Table(points) if points.len() < 2 => match points.first() {
+                Some(&(point, actual)) => raw + (actual - point),
+                None => raw,
+            },
+            Calibration::Table(points) => {
+                // Find the segment that the reading falls in, or the nearest one at either end.
+                let index = points
+                    .iter()
+                    .position(|(point, _)| point.value() > raw.value())
+                    .unwrap_or(points.len())
+                    .max(1)
+                    .min(points.len() - 1);
+                let (raw_low, actual_low) = points[index - 1];
+                let (raw_high, actual_high) = points[index];
+
+                let fraction =
+                    (raw.value() - raw_low.value()) / (raw_high.value() - raw_low.value());
+                let actual =
+                    actual_low.value() + fraction * (actual_high.value() - actual_low.value());
+                actual.into()
+            }
+        }
+    }
+
+    /// The fixed offset of the calibration, if it has one.
+    pub fn offset(&self) -> Option<CelsiusDelta> {
+        match self {
+            Calibration::Offset(offset) => Some(*offset),
+            Calibration::Linear { offset, .. } => Some(*offset),
+            Calibration::Table(_) => None,
+        }
+    }
+}
+
+/// Make sure that an offset is no bigger than [`MAX_OFFSET`].
+pub fn check_offset(offset: CelsiusDelta) -> Result<(), CalibrationError> {
+    // Written this way around so that NaN isn't believable either.
+    if offset.value().abs() <= MAX_OFFSET {
+        Ok(())
+    } else {
+        Err(CalibrationError::UnbelievableOffset(offset))
+    }
+}
+
+/// Make sure that a gain is between [`MIN_GAIN`] and [`MAX_GAIN`].
+pub fn check_gain(gain: f64) -> Result<(), CalibrationError> {
+    if (MIN_GAIN..=MAX_GAIN).contains(&gain) {
+        Ok(())
+    } else {
+        Err(CalibrationError::UnbelievableGain(gain))
+    }
+}
+
+/// Make sure that a calibration table's point is no further than [`MAX_OFFSET`] from what the
+/// sensor read.
+pub fn check_point(raw: Celsius, actual: Celsius) -> Result<(), CalibrationError> {
+    if (actual - raw).value().abs() <= MAX_OFFSET {
+        Ok(())
+    } else {
+        Err(CalibrationError::UnbelievablePoint(raw, actual))
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration::none()
+    }
+}
+
+/// A calibration, and when it took effect
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatedCalibration {
+    /// When the calibration took effect, or `None` if it has always been in effect
+    pub effective_from: Option<DateTime<Utc>>,
+    /// The calibration itself
+    pub calibration: Calibration,
+}
+
+/// Every calibration that a device has had, sorted by when they took effect.
+///
+/// Measurements taken before the first calibration took effect are left alone.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CalibrationHistory(Vec<DatedCalibration>);
+
+impl CalibrationHistory {
+    /// Build a calibration history from calibrations in any order, making sure that no two of
+    /// them take effect at the same time.
+    pub fn new(mut calibrations: Vec<DatedCalibration>) -> Result<Self, CalibrationError> {
+        // `None` sorts first, which is what we want: it has always been in effect.
+        calibrations.sort_by_key(|calibration| calibration.effective_from);
+        if let Some(pair) = calibrations
+            .windows(2)
+            .find(|pair| pair[0].effective_from == pair[1].effective_from)
+        {
+            return Err(CalibrationError::DuplicateEffectiveFrom(
+                pair[0].effective_from,
+            ));
+        }
+
+        Ok(CalibrationHistory(calibrations))
+    }
+
+    /// The calibrations, sorted by when they took effect.
+    pub fn entries(&self) -> &[DatedCalibration] {
+        &self.0
+    }
+
+    /// The calibration that was in effect at the specified date, if any.
+    pub fn at(&self, date: DateTime<Utc>) -> Option<&Calibration> {
+        self.0
+            .iter()
+            .rev()
+            .find(|calibration| match calibration.effective_from {
+                Some(effective_from) => effective_from <= date,
+                None => true,
+            })
+            .map(|calibration| &calibration.calibration)
+    }
+
+    /// The calibration that is in effect now, if any. Calibrations that take effect in the future
+    /// aren't current yet.
+    pub fn current(&self) -> Option<&Calibration> {
+        self.at(Utc::now())
+    }
+
+    /// Add a calibration to the history, replacing the one that took effect at the same time, if
+    /// there is one.
+    pub fn insert(&mut self, calibration: DatedCalibration) {
+        self.0
+            .retain(|existing| existing.effective_from != calibration.effective_from);
+        self.0.push(calibration);
+        self.0.sort_by_key(|calibration| calibration.effective_from);
+    }
+
+    /// Turn a raw reading, taken at the specified date, into the actual temperature.
+    pub fn apply(&self, raw: Celsius, date: DateTime<Utc>) -> Celsius {
+        match self.at(date) {
+            Some(calibration) => calibration.apply(raw),
+            None => raw,
+        }
+    }
+}
+
+impl From<Calibration> for CalibrationHistory {
+    /// A history with a single calibration that has always been in effect.
+    fn from(calibration: Calibration) -> Self {
+        CalibrationHistory(vec![DatedCalibration {
+            effective_from: None,
+            calibration,
+        }])
+    }
+}
@@ -0,0 +1,151 @@
+//! Newtype wrappers for the readings that sensors can report alongside temperature.
+//!
+//! Not every sensor has every channel (the DHT11 reports humidity, the BLE Sense reports
+//! pressure, and anything running on a battery can report how much is left), so a measurement's
+//! [`Channels`] are all optional.
+//!
+//! ```
+//! # use temperature_app::channels::{Channels, Humidity, Pressure};
+//! let channels = Channels {
+//!     humidity: Some(45.0.into()),
+//!     pressure: Some(1013.2.into()),
+//!     ..Channels::default()
+//! };
+//! assert!(channels.validate().is_ok());
+//! assert_eq!(channels.humidity.unwrap().to_string(), "45%");
+//! assert_eq!(format!("{:.1}", channels.pressure.unwrap()), "1013.2 hPa");
+//! assert!(channels.battery.is_none());
+//! ```
+//!
+//! Readings that can't exist are caught by validating them.
+//!
+//! ```
+//! # use temperature_app::channels::{Battery, Humidity, Pressure};
+//! assert!(Humidity::from(120.0).validate().is_err());
+//! assert!(Pressure::from(-3.0).validate().is_err());
+//! assert!(Battery::from(std::f64::NAN).validate().is_err());
+//! assert!(Battery::from(100.0).validate().is_ok());
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+/// Relative humidity, in percent
+#[derive(juniper::GraphQLScalarValue, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Humidity(f64);
+
+/// Air pressure, in hectopascals
+#[derive(juniper::GraphQLScalarValue, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pressure(f64);
+
+/// How much charge is left in a sensor's battery, in percent
+#[derive(juniper::GraphQLScalarValue, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Battery(f64);
+
+/// Errors for readings that can't exist.
+#[derive(Debug)]
+pub enum ChannelError {
+    /// The humidity was NaN, infinite, or outside of 0% to 100%.
+    Humidity(Humidity),
+    /// The pressure was NaN, infinite, or not above zero.
+    Pressure(Pressure),
+    /// The battery level was NaN, infinite, or outside of 0% to 100%.
+    Battery(Battery),
+}
+
+impl std::fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ChannelError::Humidity(humidity) => write!(
+                f,
+                "A humidity of {} is not possible. Humidity must be between 0% and 100%",
+                humidity
+            ),
+            ChannelError::Pressure(pressure) => write!(
+                f,
+                "A pressure of {} is not possible. Pressure must be above 0 hPa",
+                pressure
+            ),
+            ChannelError::Battery(battery) => write!(
+                f,
+                "A battery level of {} is not possible. Battery levels must be between 0% and \
+                 100%",
+                battery
+            ),
+        }
+    }
+}
+
+/// The parts of a channel that are the same for every channel: getting the value in and out,
+/// displaying it with its unit, and checking that it's possible.
+macro_rules! channel {
+    ($type:ident, $suffix:expr, $possible:expr) => {
+        impl $type {
+            /// Get the f64 value back from this reading.
+            pub fn value(&self) -> f64 {
+                self.0
+            }
+
+            /// Make sure that this is a reading that can actually exist.
+            pub fn validate(self) -> Result<Self, ChannelError> {
+                let possible: fn(f64) -> bool = $possible;
+                if self.0.is_finite() && possible(self.0) {
+                    Ok(self)
+                } else {
+                    Err(ChannelError::$type(self))
+                }
+            }
+        }
+
+        impl From<f64> for $type {
+            /// Mark an f64 value as being a reading of this channel.
+            fn from(value: f64) -> Self {
+                $type(value)
+            }
+        }
+
+        impl From<$type> for f64 {
+            /// Get the f64 value back from this reading.
+            fn from(value: $type) -> f64 {
+                value.0
+            }
+        }
+
+        impl std::fmt::Display for $type {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+                std::fmt::Display::fmt(&self.0, f)?;
+                f.write_str($suffix)
+            }
+        }
+    };
+}
+
+channel!(Humidity, "%", |value| (0.0..=100.0).contains(&value));
+channel!(Pressure, " hPa", |value| value > 0.0);
+channel!(Battery, "%", |value| (0.0..=100.0).contains(&value));
+
+/// The readings, other than temperature, that were taken along with a measurement.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Channels {
+    /// The relative humidity, if the sensor measures it
+    pub humidity: Option<Humidity>,
+    /// The air pressure, if the sensor measures it
+    pub pressure: Option<Pressure>,
+    /// The battery level, if the sensor reports it
+    pub battery: Option<Battery>,
+}
+
+impl Channels {
+    /// Make sure that every reading is one that can actually exist.
+    pub fn validate(self) -> Result<Self, ChannelError> {
+        if let Some(humidity) = self.humidity {
+            humidity.validate()?;
+        }
+        if let Some(pressure) = self.pressure {
+            pressure.validate()?;
+        }
+        if let Some(battery) = self.battery {
+            battery.validate()?;
+        }
+        Ok(self)
+    }
+}
@@ -0,0 +1,339 @@
+//! Line charts of temperature history, drawn as SVG
+//!
+//! For the places that can't run the homepage's JavaScript (wikis, chat bots, e-ink displays),
+//! the server draws charts itself. A [`Chart`] is a handful of lines over a stretch of time, and
+//! [`Chart::to_svg`] draws it.
+//!
+//! ```
+//! # use temperature_app::chart::{Chart, Line};
+//! # use temperature_app::temperature::TemperatureUnit;
+//! # use chrono::{Duration, TimeZone, Utc};
+//! let from = Utc.ymd(2019, 11, 1).and_hms(0, 0, 0);
+//! let chart = Chart {
+//!     from,
+//!     until: from + Duration::hours(3),
+//!     unit: TemperatureUnit::Fahrenheit,
+//!     width: 800,
+//!     height: 300,
+//!     lines: vec![Line {
+//!         name: "Basement".into(),
+//!         points: vec![
+//!             (from, 20.0.into()),
+//!             (from + Duration::hours(1), 21.0.into()),
+//!             (from + Duration::hours(2), 22.0.into()),
+//!         ],
+//!         gap: None,
+//!     }],
+//! };
+//!
+//! let svg = chart.to_svg();
+//! assert!(svg.starts_with("<svg"));
+//! assert!(svg.contains("Basement"));
+//! assert!(svg.contains("°F"));
+//! ```
+
+use crate::temperature::{Celsius, TemperatureUnit};
+use chrono::{DateTime, Duration, Utc};
+use std::fmt::Write;
+
+/// The colors that lines are drawn in, in order. Charts with more lines than this start over.
+const COLORS: [&str; 8] = [
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf",
+];
+
+/// How much room to leave around the plot for the axis labels, in pixels: left, right, top, and
+/// bottom.
+const MARGIN: (f64, f64, f64, f64) = (50.0, 10.0, 10.0, 45.0);
+
+/// How wide each entry in the legend is, and how far apart its rows are, in pixels. Entries
+/// that don't fit across the chart wrap onto more rows.
+const LEGEND_COLUMN: f64 = 150.0;
+const LEGEND_ROW: f64 = 15.0;
+
+/// Roughly how wide a character of a legend entry is, in pixels, for cutting short names that
+/// don't fit.
+const CHARACTER_WIDTH: f64 = 7.0;
+
+/// How many labels to put along each axis.
+const Y_TICKS: u32 = 4;
+const X_TICKS: u32 = 5;
+
+/// A single line on a chart: one device's temperatures over time.
+#[derive(Debug, Clone)]
+pub struct Line {
+    /// What to call the line in the legend
+    pub name: String,
+    /// The temperatures to draw, oldest first
+    pub points: Vec<(DateTime<Utc>, Celsius)>,
+    /// Points further apart than this aren't joined up, so that a device that was off for a
+    /// while leaves a gap instead of a straight line. Devices that report less often than this
+    /// aren't cut into pieces, though: points are always joined if they're no further apart than
+    /// a few times the usual spacing. Every point is joined if this is missing.
+    pub gap: Option<Duration>,
+}
+
+/// A chart of temperatures over time.
+#[derive(Debug, Clone)]
+pub struct Chart {
+    /// Where the chart starts
+    pub from: DateTime<Utc>,
+    /// Where the chart ends
+    pub until: DateTime<Utc>,
+    /// The unit to label the temperatures in
+    pub unit: TemperatureUnit,
+    /// How wide the chart is, in pixels
+    pub width: u32,
+    /// How tall the chart is, in pixels
+    pub height: u32,
+    /// The lines to draw
+    pub lines: Vec<Line>,
+}
+
+impl Chart {
+    /// Draw the chart as a standalone SVG document.
+    pub fn to_svg(&self) -> String {
+        let (left, right, top, bottom) = MARGIN;
+        let width = f64::from(self.width);
+        let height = f64::from(self.height);
+
+        // The legend goes along the bottom, under the dates, with as many entries to a row as
+        // fit. Every row after the first takes room from the plot.
+        let legend_width = (width - left - right).max(1.0);
+        let columns = ((legend_width / LEGEND_COLUMN).floor() as usize).max(1);
+        let column_width = LEGEND_COLUMN.min(legend_width);
+        let rows = self.lines.len().div_ceil(columns).max(1);
+        let bottom = bottom + LEGEND_ROW * (rows - 1) as f64;
+
+        let plot_width = (width - left - right).max(1.0);
+        let plot_height = (height - top - bottom).max(1.0);
+
+        let (low, high) = self.value_range();
+        let range = (self.until - self.from).num_milliseconds().max(1) as f64;
+        let x = |date: DateTime<Utc>| {
+            left + (date - self.from).num_milliseconds() as f64 / range * plot_width
+        };
+        let y = |value: f64| top + (high - value) / (high - low) * plot_height;
+
+        let mut svg = String::new();
+        // Writing to a String can't fail, so the results of write! are ignored throughout.
+        let _ = write!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\" font-family=\"sans-serif\" font-size=\"12\">\
+             <rect width=\"{width}\" height=\"{height}\" fill=\"white\"/>",
+            width = self.width,
+            height = self.height
+        );
+
+        for tick in 0..=Y_TICKS {
+            let value = low + (high - low) * f64::from(tick) / f64::from(Y_TICKS);
+            let _ = write!(
+                svg,
+                "<line x1=\"{:.1}\" x2=\"{:.1}\" y1=\"{y:.1}\" y2=\"{y:.1}\" stroke=\"#eee\"/>\
+                 <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\" fill=\"#666\">{:.1}{}</text>",
+                left,
+                width - right,
+                left - 5.0,
+                y(value) + 4.0,
+                value,
+                self.unit,
+                y = y(value)
+            );
+        }
+
+        let format = if self.until - self.from <= Duration::days(1) {
+            "%H:%M"
+        } else {
+            "%b %-d"
+        };
+        for tick in 0..=X_TICKS {
+            let date = self.from
+                + Duration::milliseconds((range * f64::from(tick) / f64::from(X_TICKS)) as i64);
+            // The first and last labels line up with the ends of the plot so they don't hang off
+            // the sides of the image.
+            let anchor = match tick {
+                0 => "start",
+                X_TICKS => "end",
+                _ => "middle",
+            };
+            let _ = write!(
+                svg,
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\" fill=\"#666\">{}</text>",
+                x(date),
+                top + plot_height + 15.0,
+                anchor,
+                date.format(format)
+            );
+        }
+
+        for (index, line) in self.lines.iter().enumerate() {
+            let color = COLORS[index % COLORS.len()];
+
+            let gap = line.gap.map(|gap| gap.max(line.usual_spacing() * 3));
+            let mut path = String::new();
+            let mut previous: Option<DateTime<Utc>> = None;
+            for &(date, temperature) in &line.points {
+                let joined = match (previous, gap) {
+                    (Some(previous), Some(gap)) => date - previous <= gap,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                let _ = write!(
+                    path,
+                    "{}{:.1},{:.1} ",
+                    if joined { "L" } else { "M" },
+                    x(date),
+                    y(self.unit.value_of(temperature))
+                );
+                previous = Some(date);
+            }
+            if !path.is_empty() {
+                let _ = write!(
+                    svg,
+                    "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>",
+                    path.trim_end(),
+                    color
+                );
+            }
+
+            let (row, column) = (index / columns, index % columns);
+            let legend_x = left + column_width * column as f64;
+            let legend_y = height - 8.0 - LEGEND_ROW * (rows - 1 - row) as f64;
+            let _ = write!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"12\" height=\"4\" fill=\"{}\"/>\
+                 <text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+                legend_x,
+                legend_y - 5.0,
+                color,
+                legend_x + 16.0,
+                legend_y,
+                escape(&truncate(&line.name, column_width - 20.0))
+            );
+        }
+
+        svg.push_str("</svg>");
+        svg
+    }
+
+    /// The lowest and highest temperatures to show, in the chart's unit. Charts of a steady
+    /// temperature (or of nothing at all) still get a range, so that there's something to draw.
+    fn value_range(&self) -> (f64, f64) {
+        let values = self
+            .lines
+            .iter()
+            .flat_map(|line| line.points.iter())
+            .map(|&(_, temperature)| self.unit.value_of(temperature));
+
+        let (low, high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), value| {
+            (low.min(value), high.max(value))
+        });
+
+        if low > high {
+            (0.0, 1.0)
+        } else if high - low < 1.0 {
+            (low - 0.5, high + 0.5)
+        } else {
+            (low, high)
+        }
+    }
+}
+
+impl Line {
+    /// The median time between points, which is how often the device usually reports (or how
+    /// wide the buckets are, if the points are summaries).
+    fn usual_spacing(&self) -> Duration {
+        let mut spacings: Vec<Duration> = self
+            .points
+            .windows(2)
+            .map(|pair| pair[1].0 - pair[0].0)
+            .collect();
+        spacings.sort();
+        spacings
+            .get(spacings.len() / 2)
+            .cloned()
+            .unwrap_or_else(Duration::zero)
+    }
+}
+
+/// Cut a legend entry short, with an ellipsis, if it's too wide for the room it has.
+fn truncate(name: &str, room: f64) -> String {
+    let characters = ((room / CHARACTER_WIDTH).floor() as usize).max(1);
+    if name.chars().count() <= characters {
+        name.into()
+    } else {
+        name.chars()
+            .take(characters - 1)
+            .chain(std::iter::once('…'))
+            .collect()
+    }
+}
+
+/// Escape text so that it can go inside an SVG element.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// The position and text of every legend entry in a chart.
+    fn legend(svg: &str) -> Vec<(f64, f64, String)> {
+        // Legend entries are the only text without a fill of its own.
+        svg.split("<text x=\"")
+            .skip(1)
+            .filter(|text| !text[..text.find('>').unwrap()].contains("fill="))
+            .map(|text| {
+                let (x, rest) = text.split_once("\" y=\"").unwrap();
+                let (y, rest) = rest.split_once("\">").unwrap();
+                let (name, _) = rest.split_once("</text>").unwrap();
+                (x.parse().unwrap(), y.parse().unwrap(), name.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn narrow_legends_wrap() {
+        let from = Utc.ymd(2019, 11, 1).and_hms(0, 0, 0);
+        let chart = |width| Chart {
+            from,
+            until: from + Duration::hours(3),
+            unit: TemperatureUnit::Celsius,
+            width,
+            height: 300,
+            lines: (0..5)
+                .map(|index| Line {
+                    name: format!("Upstairs bedroom {}", index),
+                    points: vec![(from, 20.0.into())],
+                    gap: None,
+                })
+                .collect(),
+        };
+
+        // Two entries fit across at this width, so five take three rows.
+        let entries = legend(&chart(400).to_svg());
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].1, entries[1].1);
+        assert!(entries[2].1 > entries[1].1);
+        assert!(entries[4].1 > entries[3].1);
+        assert_eq!(entries[4].0, entries[0].0);
+        assert!(entries.iter().all(|(x, _, _)| *x < 400.0 - MARGIN.1));
+        assert!(entries.iter().all(|(_, y, _)| *y <= 300.0));
+
+        // At the smallest width, each entry gets its own row, and names are cut short to fit.
+        let entries = legend(&chart(100).to_svg());
+        assert_eq!(entries.len(), 5);
+        for (index, (x, y, name)) in entries.iter().enumerate() {
+            assert_eq!(*x, entries[0].0);
+            assert!(index == 0 || *y > entries[index - 1].1);
+            assert!(name.ends_with('…'), "{}", name);
+            let right = x + name.chars().count() as f64 * CHARACTER_WIDTH;
+            assert!(right <= 100.0 - MARGIN.1, "{} ends at {}", name, right);
+        }
+    }
+}
@@ -3,29 +3,44 @@
 //! Provide access to an ElasticSearch database and perform key operations against the database.
 //!
 //! ```
+//! # use temperature_app::channels::Channels;
 //! # use temperature_app::database::Database;
 //! let url = url::Url::parse("http://localhost:9200").unwrap();
 //! let database = Database::new(url);
-//! let ble_address = "f4d55889b1d6";
+//! let ble_address = "f4d55889b1d6".parse().unwrap();
 //! let now = chrono::Utc::now();
 //! let temperature = 27.0.into();
-//! database.insert_measurement(ble_address, now, temperature);
+//! let channels = Channels {
+//!     humidity: Some(45.0.into()),
+//!     ..Channels::default()
+//! };
+//! database.insert_measurement(&ble_address, now, temperature, &channels);
 //! ```
 
-use crate::temperature::Celsius;
+use crate::address::BleAddress;
+use crate::calibration::{Calibration, CalibrationHistory, DatedCalibration};
+use crate::channels::Channels;
+use crate::events::EventId;
+use crate::metrics::{Counter, Histogram};
+use crate::temperature::{Celsius, PlausibleRange};
 use chrono::prelude::*;
 use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::time::Instant;
 use url::Url;
 
 /// A connection to the ElasticSearch database
 pub struct Database {
     url: Url,
     client: reqwest::Client,
+    durations: Histogram,
+    failures: Counter,
 }
 
 /// Errors that can occur when using the database.
+#[derive(Debug)]
 pub enum DatabaseError {
     /// An attempt to build a URL failed.
     InvalidUrl,
@@ -36,6 +51,8 @@ pub enum DatabaseError {
     /// The json returned from the specified endpoint did not match what we expected it to look
     /// like.
     UnexpectedResponse,
+    /// The index that was searched does not exist (yet).
+    IndexNotFound,
 }
 
 impl std::fmt::Display for DatabaseError {
@@ -49,6 +66,7 @@ impl std::fmt::Display for DatabaseError {
             DatabaseError::UnexpectedResponse => {
                 "The requested to the database returned unexpected results".fmt(f)
             }
+            DatabaseError::IndexNotFound => "The requested index does not exist".fmt(f),
         }
     }
 }
@@ -56,20 +74,78 @@ impl std::fmt::Display for DatabaseError {
 /// The result of a request for measurements from the database
 pub struct MeasurementResult {
     /// The address for this measurement
-    pub address: Option<String>,
+    pub address: Option<BleAddress>,
     /// The data that the measurement was taken
     pub date: Option<DateTime<Utc>>,
     /// The raw temperature reading at the given time
     pub temperature: Option<Celsius>,
+    /// The other readings taken at the given time
+    pub channels: Channels,
 }
 
+/// A measurement to be stored with [`Database::insert_measurements`]
+#[derive(Debug, Clone)]
+pub struct NewMeasurement {
+    /// The device that took the measurement
+    pub address: BleAddress,
+    /// When the measurement was taken. Only whole seconds are stored.
+    pub date: DateTime<Utc>,
+    /// The raw temperature reading
+    pub temperature: Celsius,
+    /// The other readings taken at the same time
+    pub channels: Channels,
+}
+
+/// What happened to one of the measurements given to [`Database::insert_measurements`]
+#[derive(Debug, PartialEq)]
+pub enum InsertResult {
+    /// The measurement was stored.
+    Inserted,
+    /// The device already had a measurement stored for that second, so this one was left out.
+    Duplicate,
+    /// ElasticSearch refused the measurement, for the given reason.
+    Failed(String),
+}
+
+/// A summary of the measurements a device took during one stretch of time
+pub struct SeriesBucket {
+    /// When the stretch of time starts
+    pub date: DateTime<Utc>,
+    /// How many measurements were taken during it
+    pub count: u64,
+    /// The lowest raw temperature reading
+    pub min: Celsius,
+    /// The average raw temperature reading
+    pub mean: Celsius,
+    /// The highest raw temperature reading
+    pub max: Celsius,
+}
+
+/// The result of a request for devices from the database
+pub struct DeviceResult {
+    /// The BLE address of the device
+    pub address: Option<BleAddress>,
+    /// The human-readable name of the device
+    pub name: Option<String>,
+    /// The human-readable description of the device
+    pub description: Option<String>,
+    /// How to turn the raw readings of the device into actual temperatures, over time
+    pub calibrations: Option<CalibrationHistory>,
+    /// The temperatures that the device can believably report
+    pub plausible_range: Option<PlausibleRange>,
+}
+
+/// The index that the device registry is kept in. Measurements are kept in one index per day, so
+/// anything that searches measurements needs to leave this one out.
+const DEVICES_INDEX: &str = "devices";
+
 /// Used internally for deserializing from ElasticSearch.
 #[derive(Debug, Serialize, Deserialize)]
-struct Hit {
+struct Hit<T> {
     _id: String,
     _index: String,
     _score: Option<f64>,
-    _source: HitSource,
+    _source: T,
     _type: String,
 }
 
@@ -78,35 +154,258 @@ struct Hit {
 struct HitSource {
     address: Option<String>,
     temp_c: Option<f64>,
+    humidity: Option<f64>,
+    pressure: Option<f64>,
+    battery: Option<f64>,
     date: Option<DateTime<Utc>>,
 }
 
+/// Used internally for deserializing the response to a bulk request from ElasticSearch.
+#[derive(Debug, Deserialize)]
+struct BulkSource {
+    items: Vec<BulkItemSource>,
+}
+
+/// Used internally for deserializing the response to a bulk request from ElasticSearch. We only
+/// ever send `create` actions.
+#[derive(Debug, Deserialize)]
+struct BulkItemSource {
+    create: BulkResultSource,
+}
+
+/// Used internally for deserializing the response to a bulk request from ElasticSearch.
+#[derive(Debug, Deserialize)]
+struct BulkResultSource {
+    status: u16,
+    error: Option<BulkErrorSource>,
+}
+
+/// Used internally for deserializing the response to a bulk request from ElasticSearch.
+#[derive(Debug, Deserialize)]
+struct BulkErrorSource {
+    reason: Option<String>,
+}
+
+/// Used internally for deserializing the buckets of a date histogram from ElasticSearch.
+#[derive(Debug, Deserialize)]
+struct BucketSource {
+    /// The start of the bucket, in milliseconds since the epoch
+    key: i64,
+    doc_count: u64,
+    min: MetricSource,
+    avg: MetricSource,
+    max: MetricSource,
+}
+
+/// Used internally for deserializing the buckets of a terms aggregation from ElasticSearch, with
+/// the latest measurement in each.
+#[derive(Debug, Deserialize)]
+struct LatestBucketSource {
+    latest: TopHitsSource,
+}
+
+/// Used internally for deserializing a top hits aggregation from ElasticSearch.
+#[derive(Debug, Deserialize)]
+struct TopHitsSource {
+    hits: TopHitsHitsSource,
+}
+
+/// Used internally for deserializing a top hits aggregation from ElasticSearch, which nests its
+/// hits the same way a search does.
+#[derive(Debug, Deserialize)]
+struct TopHitsHitsSource {
+    hits: Vec<Hit<HitSource>>,
+}
+
+/// Used internally for deserializing a single-value metric aggregation from ElasticSearch. The
+/// value is missing if none of the documents had the field.
+#[derive(Debug, Deserialize)]
+struct MetricSource {
+    value: Option<f64>,
+}
+
+/// Used internally for serializing to and deserializing from ElasticSearch.
+#[derive(Debug, Serialize, Deserialize)]
+struct DeviceSource {
+    address: Option<String>,
+    name: Option<String>,
+    description: Option<String>,
+    /// Devices stored before calibrations existed only have an adjustment, and devices stored
+    /// before calibration histories existed only have a calibration. We still write both for the
+    /// current calibration, so that the documents are easy to read.
+    adjustment: Option<f64>,
+    calibration: Option<CalibrationSource>,
+    calibrations: Option<Vec<DatedCalibrationSource>>,
+    plausible_range: Option<PlausibleRange>,
+}
+
+/// Used internally for serializing to and deserializing from ElasticSearch.
+#[derive(Debug, Serialize, Deserialize)]
+struct DatedCalibrationSource {
+    effective_from: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    calibration: CalibrationSource,
+}
+
+impl DeviceSource {
+    /// Get the calibration history back out of whichever fields the document has.
+    fn calibrations(&self) -> Option<CalibrationHistory> {
+        match (&self.calibrations, &self.calibration, self.adjustment) {
+            (Some(calibrations), _, _) => {
+                let calibrations = calibrations
+                    .iter()
+                    .filter_map(|dated| {
+                        Some(DatedCalibration {
+                            effective_from: dated.effective_from,
+                            calibration: dated.calibration.to_calibration()?,
+                        })
+                    })
+                    .collect();
+                CalibrationHistory::new(calibrations).ok()
+            }
+            (None, Some(calibration), _) => calibration.to_calibration().map(Into::into),
+            (None, None, Some(adjustment)) => Some(Calibration::Offset(adjustment.into()).into()),
+            (None, None, None) => None,
+        }
+    }
+}
+
+/// Used internally for serializing to and deserializing from ElasticSearch.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum CalibrationSource {
+    Offset { offset: f64 },
+    Linear { gain: f64, offset: f64 },
+    Table { points: Vec<(f64, f64)> },
+}
+
+impl From<&Calibration> for CalibrationSource {
+    fn from(calibration: &Calibration) -> Self {
+        match calibration {
+            Calibration::Offset(offset) => CalibrationSource::Offset {
+                offset: offset.value(),
+            },
+            Calibration::Linear { gain, offset } => CalibrationSource::Linear {
+                gain: *gain,
+                offset: offset.value(),
+            },
+            Calibration::Table(points) => CalibrationSource::Table {
+                points: points
+                    .iter()
+                    .map(|(raw, actual)| (raw.value(), actual.value()))
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl CalibrationSource {
+    /// Turn the stored calibration back into a calibration, if it's still a valid one.
+    fn to_calibration(&self) -> Option<Calibration> {
+        match *self {
+            CalibrationSource::Offset { offset } => Some(Calibration::Offset(offset.into())),
+            CalibrationSource::Linear { gain, offset } => {
+                Calibration::linear(gain, offset.into()).ok()
+            }
+            CalibrationSource::Table { ref points } => Calibration::table(
+                points
+                    .iter()
+                    .map(|&(raw, actual)| (raw.into(), actual.into()))
+                    .collect(),
+            )
+            .ok(),
+        }
+    }
+}
+
+impl From<HitSource> for MeasurementResult {
+    fn from(source: HitSource) -> Self {
+        MeasurementResult {
+            address: source.address.and_then(|address| address.parse().ok()),
+            date: source.date,
+            temperature: source.temp_c.map(|val| val.into()),
+            channels: Channels {
+                humidity: source.humidity.map(|val| val.into()),
+                pressure: source.pressure.map(|val| val.into()),
+                battery: source.battery.map(|val| val.into()),
+            },
+        }
+    }
+}
+
+/// Work out where a measurement is stored and what the stored document looks like: the index,
+/// the ID, and the document itself. Only the channels that have readings are stored.
+fn measurement_document(
+    address: &BleAddress,
+    date: DateTime<Utc>,
+    temperature: Celsius,
+    channels: &Channels,
+) -> (String, String, serde_json::Value) {
+    // Drop sub-second precision. We're only storing second resolution. It's safe to unwrap
+    // because dropping the nanosecond precision won't make this an invalid date.
+    let date = date.with_nanosecond(0).unwrap();
+    // Use the current day as the index. This way, we can drop days worth of old data.
+    let index = format!("{}", date.format("%Y%m%d"));
+    // Create an ID out of the address and the date, so that another measurement for this same
+    // exact second gets the same ID.
+    let id = format!("{}-{}", date.format("%Y%m%dT%H%M%S"), address);
+
+    let mut document = json!({
+        "address": address,
+        "date": date.to_rfc3339(),
+        "temp_c": f64::from(temperature),
+    });
+    if let Some(humidity) = channels.humidity {
+        document["humidity"] = json!(f64::from(humidity));
+    }
+    if let Some(pressure) = channels.pressure {
+        document["pressure"] = json!(f64::from(pressure));
+    }
+    if let Some(battery) = channels.battery {
+        document["battery"] = json!(f64::from(battery));
+    }
+
+    (index, id, document)
+}
+
 impl Database {
     /// Create a new database connection to the ElasticSearch database found at the specified URL.
     pub fn new(url: Url) -> Self {
         let client = reqwest::Client::new();
 
-        Database { url, client }
+        Database {
+            url,
+            client,
+            durations: Histogram::new(
+                "temperature_app_database_request_duration_seconds",
+                "How long requests to ElasticSearch took",
+            ),
+            failures: Counter::new(
+                "temperature_app_database_request_failures_total",
+                "How many requests to ElasticSearch failed",
+            ),
+        }
+    }
+
+    /// Write out how long requests to the database have taken, and how many have failed, in the
+    /// Prometheus text format.
+    pub fn write_metrics(&self, out: &mut String) {
+        self.durations.write(out);
+        self.failures.write(out);
     }
 
-    /// Insert a measurement into the database.
+    /// Insert a measurement into the database. Only the channels that have readings are stored.
     ///
     /// Note that we store data in one-second resolution, so inserting multiple times per second
     /// will result in updated values instead of new, distinct values.
     pub fn insert_measurement(
         &self,
-        address: &str,
+        address: &BleAddress,
         date: DateTime<Utc>,
         temperature: Celsius,
+        channels: &Channels,
     ) -> Result<(), DatabaseError> {
-        // Drop sub-second precision. We're only storing second resolution. It's safe to unwrap
-        // because dropping the nanosecond precision won't make this an invalid date.
-        let date = date.with_nanosecond(0).unwrap();
-        // Use the current day as the index. This way, we can drop days worth of old data.
-        let index = format!("{}", date.format("%Y%m%d"));
-        // Create an ID out of the address and the date. If we get another measurement for this
-        // same exact second, we will overwrite rather than add.
-        let id = format!("{}-{}", date.format("%Y%m%dT%H%M%S"), address);
+        let (index, id, document) = measurement_document(address, date, temperature, channels);
         // Join those to make a full path.
         let path = format!("{}/_doc/{}", index, id);
         // Build the PUT url.
@@ -116,22 +415,87 @@ impl Database {
         };
 
         // Put the data into elasticsearch.
-        let result = self
-            .client
-            .put(url.as_str())
-            .json(&json!({
-                "address": address,
-                "date": date.to_rfc3339(),
-                "temp_c": f64::from(temperature),
-            }))
-            .send();
+        let result = self.send("insert", self.client.put(url.as_str()).json(&document));
 
         match result {
-            Ok(_) => Ok(()),
-            Err(_) => Err(DatabaseError::RequestFailed),
+            Ok(ref response) if response.status().is_success() => Ok(()),
+            _ => Err(DatabaseError::RequestFailed),
         }
     }
 
+    /// Insert many measurements into the database in a single request, and report what happened
+    /// to each one, in order.
+    ///
+    /// Unlike [`Database::insert_measurement`], measurements never replace one that is already
+    /// stored for the same device and second. They're reported as duplicates instead, so loading
+    /// the same history twice doesn't change anything.
+    pub fn insert_measurements(
+        &self,
+        measurements: &[NewMeasurement],
+    ) -> Result<Vec<InsertResult>, DatabaseError> {
+        if measurements.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let url = match self.url.join("/_bulk") {
+            Ok(url) => url,
+            Err(_) => return Err(DatabaseError::InvalidUrl),
+        };
+
+        // The bulk API takes newline-delimited JSON: an action line, then the document.
+        let mut body = String::new();
+        for measurement in measurements {
+            let (index, id, document) = measurement_document(
+                &measurement.address,
+                measurement.date,
+                measurement.temperature,
+                &measurement.channels,
+            );
+            body.push_str(&json!({ "create": { "_index": index, "_id": id } }).to_string());
+            body.push('\n');
+            body.push_str(&document.to_string());
+            body.push('\n');
+        }
+
+        let result = self.send(
+            "bulk_insert",
+            self.client
+                .post(url.as_str())
+                .header("content-type", "application/x-ndjson")
+                .body(body),
+        );
+
+        let mut response = match result {
+            Ok(response) => response,
+            Err(_) => return Err(DatabaseError::RequestFailed),
+        };
+        if !response.status().is_success() {
+            return Err(DatabaseError::RequestFailed);
+        }
+        let response: BulkSource = match response.json() {
+            Ok(response) => response,
+            Err(_) => return Err(DatabaseError::InvalidJson),
+        };
+        if response.items.len() != measurements.len() {
+            return Err(DatabaseError::UnexpectedResponse);
+        }
+
+        Ok(response
+            .items
+            .into_iter()
+            .map(|item| match item.create.status {
+                200..=299 => InsertResult::Inserted,
+                409 => InsertResult::Duplicate,
+                status => InsertResult::Failed(
+                    item.create
+                        .error
+                        .and_then(|error| error.reason)
+                        .unwrap_or_else(|| format!("status {}", status)),
+                ),
+            })
+            .collect())
+    }
+
     /// Get measurements for the specified device
     ///
     /// TODO: If this went further, there would have to be more control here like order, limit,
@@ -140,42 +504,352 @@ impl Database {
     /// values.
     pub fn select_measurements_for_device(
         &self,
-        address: &str,
+        address: &BleAddress,
         limit: u32,
     ) -> Result<Vec<MeasurementResult>, DatabaseError> {
-        let url = match self.url.join("/*/_search") {
-            Ok(url) => url,
-            Err(_) => return Err(DatabaseError::InvalidUrl),
-        };
+        let mut measurements = self.search_measurements(json!({
+            "size": limit,
+            "sort": {
+                "date": "desc",
+            },
+            "query": {
+                "bool" : {
+                    "filter" : {
+                        "term" : { "address" : address },
+                    }
+                }
+            }
+        }))?;
+
+        measurements.reverse();
 
-        let result = self
-            .client
-            .post(url.as_str())
-            .json(&json!({
-                "size": limit,
-                "sort": {
-                    "date": "desc",
+        Ok(measurements)
+    }
+
+    /// Get the most recent measurement of each of the specified devices, in a single search.
+    /// Devices that haven't taken any measurements are left out.
+    ///
+    /// The search is sent even when there are no devices, so that this doubles as a check that
+    /// the database is answering.
+    pub fn select_latest_measurements(
+        &self,
+        addresses: &[BleAddress],
+    ) -> Result<Vec<MeasurementResult>, DatabaseError> {
+        let path = format!("/*,-{}/_search", DEVICES_INDEX);
+        let response = self.post_search(
+            &path,
+            json!({
+                "size": 0,
+                // Addresses are mapped as text, with a keyword version for exact matches.
+                "query": {
+                    "bool" : {
+                        "filter" : { "terms": { "address.keyword": addresses } },
+                    }
                 },
+                "aggs": {
+                    "devices": {
+                        "terms": {
+                            "field": "address.keyword",
+                            "size": addresses.len().max(1),
+                        },
+                        "aggs": {
+                            "latest": {
+                                "top_hits": {
+                                    "size": 1,
+                                    "sort": { "date": "desc" },
+                                }
+                            }
+                        }
+                    }
+                }
+            }),
+        )?;
+
+        let buckets = match response.pointer("/aggregations/devices/buckets") {
+            Some(buckets) => buckets.clone(),
+            None => return Err(DatabaseError::UnexpectedResponse),
+        };
+        let buckets: Vec<LatestBucketSource> = match serde_json::value::from_value(buckets) {
+            Ok(buckets) => buckets,
+            Err(_) => return Err(DatabaseError::UnexpectedResponse),
+        };
+
+        Ok(buckets
+            .into_iter()
+            .filter_map(|bucket| bucket.latest.hits.hits.into_iter().next())
+            .map(|hit| hit._source.into())
+            .collect())
+    }
+
+    /// Get the measurements for the specified device taken between two dates (inclusive), oldest
+    /// first. At most `limit` measurements are returned.
+    pub fn select_measurements_between(
+        &self,
+        address: &BleAddress,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<MeasurementResult>, DatabaseError> {
+        self.search_measurements(json!({
+            "size": limit,
+            "sort": {
+                "date": "asc",
+            },
+            "query": {
+                "bool" : {
+                    "filter" : [
+                        { "term": { "address": address } },
+                        { "range": { "date": { "gte": from.to_rfc3339(), "lte": until.to_rfc3339() } } },
+                    ],
+                }
+            }
+        }))
+    }
+
+    /// Summarize the measurements for the specified device taken from one date up to (but not
+    /// including) another, in buckets of `interval` lined up with `origin`, oldest first. Buckets
+    /// with no measurements are left out, so a device that was off for a while leaves a gap.
+    ///
+    /// Buckets are lined up with `origin` rather than `from` so that a range can be summarized in
+    /// parts that share buckets. Usually, `origin` is just `from`.
+    ///
+    /// This is how long stretches of history are read: a month of measurements is far more than a
+    /// search will return, but a few hundred buckets of it isn't.
+    pub fn select_series(
+        &self,
+        address: &BleAddress,
+        origin: DateTime<Utc>,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+        interval: chrono::Duration,
+    ) -> Result<Vec<SeriesBucket>, DatabaseError> {
+        // ElasticSearch lines buckets up with the epoch, unless we tell it otherwise.
+        let interval = interval.num_seconds().max(1);
+        let offset = origin.timestamp().rem_euclid(interval);
+
+        let path = format!("/*,-{}/_search", DEVICES_INDEX);
+        let response = self.post_search(
+            &path,
+            json!({
+                "size": 0,
                 "query": {
                     "bool" : {
-                        "filter" : {
-                            "term" : { "address" : address },
+                        "filter" : [
+                            { "term": { "address": address } },
+                            { "range": { "date": { "gte": from.to_rfc3339(), "lt": until.to_rfc3339() } } },
+                        ],
+                    }
+                },
+                "aggs": {
+                    "series": {
+                        "date_histogram": {
+                            "field": "date",
+                            "fixed_interval": format!("{}s", interval),
+                            "offset": format!("+{}s", offset),
+                            "min_doc_count": 1,
+                        },
+                        "aggs": {
+                            "min": { "min": { "field": "temp_c" } },
+                            "avg": { "avg": { "field": "temp_c" } },
+                            "max": { "max": { "field": "temp_c" } },
                         }
                     }
                 }
-            }))
-            .send();
+            }),
+        )?;
 
-        let mut response = match result {
-            Ok(response) => response,
-            Err(_) => return Err(DatabaseError::RequestFailed),
+        let buckets = match response.pointer("/aggregations/series/buckets") {
+            Some(buckets) => buckets.clone(),
+            None => return Err(DatabaseError::UnexpectedResponse),
+        };
+        let buckets: Vec<BucketSource> = match serde_json::value::from_value(buckets) {
+            Ok(buckets) => buckets,
+            Err(_) => return Err(DatabaseError::UnexpectedResponse),
         };
 
-        let value: serde_json::Value = match response.json() {
-            Ok(value) => value,
-            Err(_) => return Err(DatabaseError::InvalidJson),
+        Ok(buckets
+            .into_iter()
+            .filter_map(|bucket| {
+                Some(SeriesBucket {
+                    date: Utc.timestamp_millis_opt(bucket.key).single()?,
+                    count: bucket.doc_count,
+                    min: bucket.min.value?.into(),
+                    mean: bucket.avg.value?.into(),
+                    max: bucket.max.value?.into(),
+                })
+            })
+            .collect())
+    }
+
+    /// Get the measurements that come after the specified event, oldest first, and in order of
+    /// address within the same second (the same order that event IDs sort in).
+    ///
+    /// If an address is given, only measurements for that device are returned. At most `limit`
+    /// measurements are returned, so a caller that is catching up on a long gap gets the oldest
+    /// part of it, and can ask again from the last one to get the rest.
+    pub fn select_measurements_after(
+        &self,
+        address: Option<&BleAddress>,
+        after: &EventId,
+        limit: u32,
+    ) -> Result<Vec<MeasurementResult>, DatabaseError> {
+        let mut filters = vec![json!({
+            "range": { "date": { "gte": after.timestamp * 1000, "format": "epoch_millis" } },
+        })];
+        if let Some(address) = address {
+            filters.push(json!({
+                "term": { "address": address },
+            }));
+        }
+
+        self.search_measurements(json!({
+            "size": limit,
+            // Addresses are mapped as text, with a keyword version for sorting.
+            "sort": [
+                { "date": "asc" },
+                { "address.keyword": "asc" },
+            ],
+            // Dates sort as milliseconds since the epoch.
+            "search_after": [after.timestamp * 1000, after.address],
+            "query": {
+                "bool" : {
+                    "filter" : filters,
+                }
+            }
+        }))
+    }
+
+    /// Insert or replace a device in the device registry.
+    pub fn upsert_device(
+        &self,
+        address: &BleAddress,
+        name: Option<&str>,
+        description: Option<&str>,
+        calibrations: &CalibrationHistory,
+        plausible_range: Option<&PlausibleRange>,
+    ) -> Result<(), DatabaseError> {
+        let path = format!("{}/_doc/{}", DEVICES_INDEX, address);
+        let url = match self.url.join(&path) {
+            Ok(url) => url,
+            Err(_) => return Err(DatabaseError::InvalidUrl),
+        };
+
+        let source = DeviceSource {
+            address: Some(address.to_string()),
+            name: name.map(|name| name.into()),
+            description: description.map(|description| description.into()),
+            adjustment: match calibrations.current() {
+                Some(Calibration::Offset(offset)) => Some(offset.value()),
+                _ => None,
+            },
+            calibration: calibrations.current().map(Into::into),
+            calibrations: Some(
+                calibrations
+                    .entries()
+                    .iter()
+                    .map(|dated| DatedCalibrationSource {
+                        effective_from: dated.effective_from,
+                        calibration: (&dated.calibration).into(),
+                    })
+                    .collect(),
+            ),
+            plausible_range: plausible_range.cloned(),
+        };
+
+        // Refresh, so that somebody listing devices right after this sees the change.
+        let result = self.send(
+            "upsert_device",
+            self.client
+                .put(url.as_str())
+                .query(&[("refresh", "true")])
+                .json(&source),
+        );
+
+        match result {
+            Ok(ref response) if response.status().is_success() => Ok(()),
+            _ => Err(DatabaseError::RequestFailed),
+        }
+    }
+
+    /// Remove a device from the device registry. Returns whether the device was there to remove.
+    ///
+    /// This only forgets what we know about the device. Its measurements stay where they are.
+    pub fn delete_device(&self, address: &BleAddress) -> Result<bool, DatabaseError> {
+        let path = format!("{}/_doc/{}", DEVICES_INDEX, address);
+        let url = match self.url.join(&path) {
+            Ok(url) => url,
+            Err(_) => return Err(DatabaseError::InvalidUrl),
+        };
+
+        let result = self.send(
+            "delete_device",
+            self.client
+                .delete(url.as_str())
+                .query(&[("refresh", "true")]),
+        );
+
+        match result {
+            Ok(ref response) if response.status().is_success() => Ok(true),
+            Ok(ref response) if response.status() == reqwest::StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(DatabaseError::RequestFailed),
+        }
+    }
+
+    /// Get every device in the device registry.
+    pub fn select_devices(&self) -> Result<Vec<DeviceResult>, DatabaseError> {
+        let path = format!("/{}/_search", DEVICES_INDEX);
+        // There are only ever going to be a handful of devices, so there's no need to page.
+        let query = json!({
+            "size": 1000,
+            "query": { "match_all": {} },
+        });
+
+        let items: Vec<Hit<DeviceSource>> = match self.search(&path, query) {
+            Ok(items) => items,
+            // The registry index doesn't exist until the first device is stored, and that just
+            // means there are no devices yet.
+            Err(DatabaseError::IndexNotFound) => Vec::new(),
+            Err(e) => return Err(e),
         };
 
+        let devices = items
+            .into_iter()
+            .map(|hit| DeviceResult {
+                calibrations: hit._source.calibrations(),
+                plausible_range: hit._source.plausible_range,
+                address: hit._source.address.and_then(|address| address.parse().ok()),
+                name: hit._source.name,
+                description: hit._source.description,
+            })
+            .collect();
+
+        Ok(devices)
+    }
+
+    /// Run a search against all of the measurement indexes, and return the measurements in the
+    /// order that ElasticSearch returned them.
+    fn search_measurements(
+        &self,
+        query: serde_json::Value,
+    ) -> Result<Vec<MeasurementResult>, DatabaseError> {
+        let path = format!("/*,-{}/_search", DEVICES_INDEX);
+        let items: Vec<Hit<HitSource>> = self.search(&path, query)?;
+
+        let measurements: Vec<MeasurementResult> =
+            items.into_iter().map(|hit| hit._source.into()).collect();
+
+        Ok(measurements)
+    }
+
+    /// Run a search against the specified path, and return the hits in the order that
+    /// ElasticSearch returned them.
+    fn search<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: serde_json::Value,
+    ) -> Result<Vec<Hit<T>>, DatabaseError> {
+        let value = self.post_search(path, query)?;
+
         // ElasticSearch returns the data as a hits top-level key, which is an object that contains
         // another hits key, which is then the array of hits.
         let hits: &serde_json::Value = match value.get("hits") {
@@ -189,22 +863,66 @@ impl Database {
         let hits: serde_json::Value = hits.clone();
 
         // Now use serde to transform all of the actual hits into our internal Hit type.
-        let items: Vec<Hit> = match serde_json::value::from_value(hits) {
+        let items: Vec<Hit<T>> = match serde_json::value::from_value(hits) {
             Ok(hits) => hits,
             Err(_) => return Err(DatabaseError::UnexpectedResponse),
         };
 
-        let mut measurements: Vec<MeasurementResult> = items
-            .into_iter()
-            .map(|hit| MeasurementResult {
-                address: hit._source.address,
-                date: hit._source.date,
-                temperature: hit._source.temp_c.map(|val| val.into()),
-            })
-            .collect();
+        Ok(items)
+    }
 
-        measurements.reverse();
+    /// Send a request to ElasticSearch, keeping track of how long it took and whether it failed.
+    /// Missing indexes and documents are an answer, not a failure.
+    fn send(
+        &self,
+        operation: &str,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        let start = Instant::now();
+        let result = request.send();
+        let labels = [("operation", operation)];
+        self.durations.observe_duration(&labels, start.elapsed());
 
-        Ok(measurements)
+        let failed = match result {
+            Ok(ref response) => {
+                let status = response.status();
+                status.is_server_error()
+                    || (status.is_client_error() && status != reqwest::StatusCode::NOT_FOUND)
+            }
+            Err(_) => true,
+        };
+        if failed {
+            self.failures.inc(&labels);
+        }
+
+        result
+    }
+
+    /// Run a search against the specified path, and return the whole response.
+    fn post_search(
+        &self,
+        path: &str,
+        query: serde_json::Value,
+    ) -> Result<serde_json::Value, DatabaseError> {
+        let url = match self.url.join(path) {
+            Ok(url) => url,
+            Err(_) => return Err(DatabaseError::InvalidUrl),
+        };
+
+        let result = self.send("search", self.client.post(url.as_str()).json(&query));
+
+        let mut response = match result {
+            Ok(response) => response,
+            Err(_) => return Err(DatabaseError::RequestFailed),
+        };
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(DatabaseError::IndexNotFound);
+        }
+
+        match response.json() {
+            Ok(value) => Ok(value),
+            Err(_) => Err(DatabaseError::InvalidJson),
+        }
     }
 }
@@ -0,0 +1,230 @@
+//! A feed of measurements as they are accepted
+//!
+//! Every measurement that comes in through the `addMeasurement` mutation is published to a
+//! [`Broadcaster`], and anybody who is interested (for example, the `/events` Server-Sent Events
+//! route, or the [`sinks`](crate::sinks) that forward measurements to other databases) can
+//! subscribe to get a copy of each one.
+//!
+//! ```
+//! # use temperature_app::events::{Broadcaster, MeasurementEvent};
+//! # use futures::Stream;
+//! let broadcaster = Broadcaster::new();
+//! let subscription = broadcaster.subscribe();
+//!
+//! broadcaster.publish(MeasurementEvent {
+//!     address: "f4d55889b1d6".parse().unwrap(),
+//!     name: Some("Basement".into()),
+//!     date: chrono::Utc::now(),
+//!     temp_c: 20.0,
+//!     temp_f: 68.0,
+//!     plausible: true,
+//!     humidity: Some(45.0),
+//!     pressure: None,
+//!     battery: None,
+//! });
+//!
+//! let event = subscription.wait().next().unwrap().unwrap();
+//! assert_eq!(event.temp_f, 68.0);
+//! ```
+
+use crate::address::BleAddress;
+use chrono::{DateTime, Utc};
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use serde::Serialize;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+
+/// A single measurement, in the shape that we send it to subscribers.
+#[derive(Debug, Clone, Serialize)]
+pub struct MeasurementEvent {
+    /// The BLE address of the device that took the measurement
+    pub address: BleAddress,
+    /// The human-readable name of the device, if available.
+    pub name: Option<String>,
+    /// The date and time that the measurement was taken
+    pub date: DateTime<Utc>,
+    /// The adjusted temperature, in degrees celsius
+    pub temp_c: f64,
+    /// The adjusted temperature, in degrees fahrenheit
+    pub temp_f: f64,
+    /// Whether the temperature is within the plausible range of the device
+    pub plausible: bool,
+    /// The relative humidity, in percent, if the device measures it
+    pub humidity: Option<f64>,
+    /// The air pressure, in hectopascals, if the device measures it
+    pub pressure: Option<f64>,
+    /// The battery level, in percent, if the device reports it
+    pub battery: Option<f64>,
+}
+
+impl MeasurementEvent {
+    /// The ID that this measurement is sent to subscribers with.
+    pub fn id(&self) -> EventId {
+        EventId {
+            timestamp: self.date.timestamp(),
+            address: self.address.clone(),
+        }
+    }
+}
+
+/// A unique ID for a measurement event, like `1572609600-f4d55889b1d6`.
+///
+/// Measurements are only stored to the second, and a device only has one measurement in each
+/// second, so the timestamp and the address together pick out exactly one measurement. IDs sort
+/// by timestamp first, so a subscriber that reconnects with the last ID it saw can be sent
+/// everything after it, including measurements from other devices in the same second.
+///
+/// ```
+/// # use temperature_app::events::EventId;
+/// let first: EventId = "1572609600-d0f7083ca3b1".parse().unwrap();
+/// let second: EventId = "1572609600-f4d55889b1d6".parse().unwrap();
+/// let third: EventId = "1572609601-d0f7083ca3b1".parse().unwrap();
+/// assert!(first < second && second < third);
+/// assert_eq!(second.to_string(), "1572609600-f4d55889b1d6");
+/// assert!("1572609600".parse::<EventId>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventId {
+    /// When the measurement was taken, in seconds since the UNIX epoch
+    pub timestamp: i64,
+    /// The device that took the measurement
+    pub address: BleAddress,
+}
+
+impl std::fmt::Display for EventId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}-{}", self.timestamp, self.address)
+    }
+}
+
+impl std::str::FromStr for EventId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("\"{}\" is not a measurement event ID", s);
+        // Timestamps before 1970 are negative, so split at the last dash rather than the first.
+        let (timestamp, address) = s.trim().rsplit_once('-').ok_or_else(invalid)?;
+        Ok(EventId {
+            timestamp: timestamp.parse().map_err(|_| invalid())?,
+            address: address.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Somebody who wants a copy of every measurement
+enum Subscriber {
+    /// A subscriber that can fall as far behind as it likes
+    Unbounded(UnboundedSender<MeasurementEvent>),
+    /// A subscriber that only gets measurements while it has room for them
+    Bounded {
+        /// What to call the subscriber when complaining about it
+        name: String,
+        sender: SyncSender<MeasurementEvent>,
+        /// How many measurements it missed because it didn't have room
+        dropped: u64,
+    },
+}
+
+impl Subscriber {
+    /// Send a measurement to the subscriber, and return whether it's still listening.
+    fn send(&mut self, event: MeasurementEvent) -> bool {
+        match self {
+            Subscriber::Unbounded(sender) => sender.unbounded_send(event).is_ok(),
+            Subscriber::Bounded {
+                name,
+                sender,
+                dropped,
+            } => match sender.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    *dropped += 1;
+                    // Complain at 1, 2, 4, 8, ... so that a subscriber that's down for a long
+                    // time doesn't fill the log.
+                    if dropped.is_power_of_two() {
+                        eprintln!(
+                            "{} is falling behind, so {} measurements have been dropped for it",
+                            name, dropped
+                        );
+                    }
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+        }
+    }
+}
+
+/// Hands out a copy of every published measurement to every current subscriber.
+pub struct Broadcaster {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl Broadcaster {
+    /// Create a new broadcaster with no subscribers.
+    pub fn new() -> Self {
+        Broadcaster {
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Subscribe to all measurements published from now on.
+    ///
+    /// Dropping the receiver unsubscribes; the broadcaster notices the next time it publishes.
+    pub fn subscribe(&self) -> UnboundedReceiver<MeasurementEvent> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(Subscriber::Unbounded(sender));
+        receiver
+    }
+
+    /// Subscribe to all measurements published from now on, keeping at most `capacity` of them
+    /// waiting at a time. Measurements that come in while the subscriber is full are dropped (and
+    /// complained about, using `name`), so that a slow subscriber never holds up the publisher.
+    ///
+    /// ```
+    /// # use temperature_app::events::{Broadcaster, MeasurementEvent};
+    /// # let event = MeasurementEvent {
+    /// #     address: "f4d55889b1d6".parse().unwrap(),
+    /// #     name: None,
+    /// #     date: chrono::Utc::now(),
+    /// #     temp_c: 20.0,
+    /// #     temp_f: 68.0,
+    /// #     plausible: true,
+    /// #     humidity: None,
+    /// #     pressure: None,
+    /// #     battery: None,
+    /// # };
+    /// let broadcaster = Broadcaster::new();
+    /// let subscription = broadcaster.subscribe_bounded("An example", 2);
+    ///
+    /// for _ in 0..3 {
+    ///     broadcaster.publish(event.clone());
+    /// }
+    /// assert_eq!(subscription.try_iter().count(), 2);
+    /// ```
+    pub fn subscribe_bounded(&self, name: &str, capacity: usize) -> Receiver<MeasurementEvent> {
+        let (sender, receiver) = sync_channel(capacity);
+        self.subscribers.lock().unwrap().push(Subscriber::Bounded {
+            name: name.into(),
+            sender,
+            dropped: 0,
+        });
+        receiver
+    }
+
+    /// Send a measurement to all of the current subscribers.
+    pub fn publish(&self, event: MeasurementEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        // Sending only fails when the receiving end has gone away, so that's our cue to forget
+        // about the subscriber.
+        subscribers.retain_mut(|subscriber| subscriber.send(event.clone()));
+    }
+}
+
+impl Default for Broadcaster {
+    fn default() -> Self {
+        Broadcaster::new()
+    }
+}
@@ -0,0 +1,331 @@
+//! Measurement history as spreadsheets
+//!
+//! An [`Export`] reads every measurement that some devices took over a stretch of time, oldest
+//! first, a page at a time, so that a year of history never has to fit in memory at once. Each
+//! [`ExportRow`] has the raw reading as well as the calibrated one, and can be written out as a
+//! line of CSV or of newline-delimited JSON.
+//!
+//! ```
+//! # use temperature_app::export::{ExportFormat, ExportRow};
+//! let row = ExportRow {
+//!     date: "2019-11-01T12:00:00Z".parse().unwrap(),
+//!     address: "f4d55889b1d6".parse().unwrap(),
+//!     name: Some("Basement, by the furnace".into()),
+//!     raw_c: 20.0,
+//!     raw_f: 68.0,
+//!     temp_c: 21.0,
+//!     temp_f: 69.8,
+//!     plausible: true,
+//!     humidity: Some(45.0),
+//!     pressure: None,
+//!     battery: None,
+//! };
+//!
+//! let csv: ExportFormat = "csv".parse().unwrap();
+//! assert_eq!(
+//!     csv.header().unwrap(),
+//!     "date,address,name,raw_c,raw_f,temp_c,temp_f,plausible,humidity,pressure,battery\n"
+//! );
+//! assert_eq!(
+//!     csv.line(&row),
+//!     "2019-11-01T12:00:00+00:00,f4d55889b1d6,\"Basement, by the furnace\",20,68,21,69.8,true,\
+//!      45,,\n"
+//! );
+//!
+//! let ndjson: ExportFormat = "ndjson".parse().unwrap();
+//! assert!(ndjson.header().is_none());
+//! assert!(ndjson.line(&row).starts_with("{\"date\":\"2019-11-01T12:00:00Z\""));
+//! ```
+
+use crate::address::BleAddress;
+use crate::calibration::CalibrationHistory;
+use crate::database::{Database, DatabaseError, MeasurementResult};
+use crate::graphql::Device;
+use crate::temperature::Fahrenheit;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+/// How many measurements to ask the database for at a time, for each device.
+const PAGE_SIZE: u32 = 1000;
+
+/// The ways that an export can be written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// Comma-separated values, with a header row
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+/// Errors for export formats that don't exist.
+#[derive(Debug)]
+pub enum ExportError {
+    /// The format wasn't one of `csv`, `json`, or `ndjson`.
+    UnknownFormat(String),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ExportError::UnknownFormat(format) => write!(
+                f,
+                "\"{}\" is not an export format. Use csv or ndjson",
+                format
+            ),
+        }
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = ExportError;
+
+    /// Parse a format, like `csv` or `ndjson`, in any case. `json` is taken to mean `ndjson`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "ndjson" | "json" | "jsonl" => Ok(ExportFormat::Ndjson),
+            _ => Err(ExportError::UnknownFormat(s.trim().into())),
+        }
+    }
+}
+
+impl ExportFormat {
+    /// The MIME type to serve the export as.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// The file extension to save the export with.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    /// The line that goes before all of the rows, if the format has one.
+    pub fn header(self) -> Option<&'static str> {
+        match self {
+            ExportFormat::Csv => Some(
+                "date,address,name,raw_c,raw_f,temp_c,temp_f,plausible,humidity,pressure,battery\n",
+            ),
+            ExportFormat::Ndjson => None,
+        }
+    }
+
+    /// Write out a single row, including the newline at the end.
+    pub fn line(self, row: &ExportRow) -> String {
+        match self {
+            ExportFormat::Csv => {
+                let optional = |value: Option<f64>| value.map_or(String::new(), |v| v.to_string());
+                format!(
+                    "{},{},{},{},{},{},{},{},{},{},{}\n",
+                    row.date.to_rfc3339(),
+                    row.address,
+                    csv_field(row.name.as_deref().unwrap_or("")),
+                    row.raw_c,
+                    row.raw_f,
+                    row.temp_c,
+                    row.temp_f,
+                    row.plausible,
+                    optional(row.humidity),
+                    optional(row.pressure),
+                    optional(row.battery)
+                )
+            }
+            ExportFormat::Ndjson => {
+                // Serializing plain numbers and strings can't fail.
+                let mut line = serde_json::to_string(row).unwrap();
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+/// A single measurement, in the shape that it's exported in.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportRow {
+    /// The date and time that the measurement was taken
+    pub date: DateTime<Utc>,
+    /// The BLE address of the device that took the measurement
+    pub address: BleAddress,
+    /// The human-readable name of the device, if available.
+    pub name: Option<String>,
+    /// The temperature the device reported, in degrees celsius
+    pub raw_c: f64,
+    /// The temperature the device reported, in degrees fahrenheit
+    pub raw_f: f64,
+    /// The temperature with the device's calibration applied, in degrees celsius
+    pub temp_c: f64,
+    /// The temperature with the device's calibration applied, in degrees fahrenheit
+    pub temp_f: f64,
+    /// Whether the temperature is within the plausible range of the device
+    pub plausible: bool,
+    /// The relative humidity, in percent, if the device measures it
+    pub humidity: Option<f64>,
+    /// The air pressure, in hectopascals, if the device measures it
+    pub pressure: Option<f64>,
+    /// The battery level, in percent, if the device reports it
+    pub battery: Option<f64>,
+}
+
+/// Every measurement that some devices took between two dates (inclusive), oldest first.
+///
+/// Measurements are read from the database a page at a time as the iterator is advanced, so the
+/// whole range is never held in memory. If reading a page fails, the error is returned and the
+/// export ends.
+pub struct Export {
+    database: Arc<Database>,
+    until: DateTime<Utc>,
+    pages: Vec<Pages>,
+    failed: bool,
+}
+
+/// The measurements of a single device that have been read but not exported yet, and where to
+/// read the next page from.
+struct Pages {
+    device: Device,
+    next: Option<DateTime<Utc>>,
+    buffer: VecDeque<ExportRow>,
+}
+
+impl Export {
+    /// Export the measurements of the devices with the given addresses. The names, calibrations,
+    /// and plausible ranges in the device registry are used to fill in the rows; devices that
+    /// aren't in the registry are exported as they were measured.
+    pub fn new(
+        database: Arc<Database>,
+        addresses: &[BleAddress],
+        devices: &BTreeMap<BleAddress, Device>,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Self {
+        let pages = addresses
+            .iter()
+            .map(|address| Pages {
+                device: devices.get(address).cloned().unwrap_or_else(|| Device {
+                    address: address.clone(),
+                    name: None,
+                    description: None,
+                    calibrations: CalibrationHistory::default(),
+                    plausible_range: None,
+                }),
+                next: Some(from),
+                buffer: VecDeque::new(),
+            })
+            .collect();
+
+        Export {
+            database,
+            until,
+            pages,
+            failed: false,
+        }
+    }
+
+    /// Make sure that every device that has measurements left has at least one read.
+    fn fill(&mut self) -> Result<(), DatabaseError> {
+        for pages in &mut self.pages {
+            if !pages.buffer.is_empty() {
+                continue;
+            }
+            let from = match pages.next {
+                Some(from) if from <= self.until => from,
+                _ => continue,
+            };
+
+            let measurements = self.database.select_measurements_between(
+                &pages.device.address,
+                from,
+                self.until,
+                PAGE_SIZE,
+            )?;
+
+            // Dates only go down to the second, and a device has at most one measurement each
+            // second, so the next page starts a second after the last measurement of this one.
+            pages.next = if measurements.len() < PAGE_SIZE as usize {
+                None
+            } else {
+                measurements
+                    .last()
+                    .and_then(|measurement| measurement.date)
+                    .map(|date| date + Duration::seconds(1))
+            };
+            let device = &pages.device;
+            pages.buffer.extend(
+                measurements
+                    .into_iter()
+                    .filter_map(|measurement| row(device, measurement)),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for Export {
+    type Item = Result<ExportRow, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if let Err(e) = self.fill() {
+            self.failed = true;
+            return Some(Err(e));
+        }
+
+        // Take the oldest of the devices' next measurements, so the devices are interleaved.
+        self.pages
+            .iter_mut()
+            .filter(|pages| !pages.buffer.is_empty())
+            .min_by_key(|pages| pages.buffer[0].date)
+            .and_then(|pages| pages.buffer.pop_front())
+            .map(Ok)
+    }
+}
+
+/// Turn a measurement into a row, if it has everything a row needs.
+fn row(device: &Device, measurement: MeasurementResult) -> Option<ExportRow> {
+    let date = measurement.date?;
+    let raw = measurement.temperature?;
+    let temperature = device.calibrations.apply(raw, date);
+
+    Some(ExportRow {
+        date,
+        address: device.address.clone(),
+        name: device.name.clone(),
+        raw_c: raw.value(),
+        raw_f: round(Fahrenheit::from(raw).value()),
+        temp_c: round(temperature.value()),
+        temp_f: round(Fahrenheit::from(temperature).value()),
+        plausible: device
+            .plausible_range
+            .as_ref()
+            .is_none_or(|range| range.contains(temperature)),
+        humidity: measurement.channels.humidity.map(f64::from),
+        pressure: measurement.channels.pressure.map(f64::from),
+        battery: measurement.channels.battery.map(f64::from),
+    })
+}
+
+/// Round a converted or calibrated temperature to a few decimal places, so that the spreadsheet
+/// says 68.54 instead of 68.53999999999999.
+fn round(value: f64) -> f64 {
+    (value * 10_000.0).round() / 10_000.0
+}
+
+/// Quote a CSV field if it needs it.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.into()
+    }
+}
@@ -1,65 +1,97 @@
 //! All the bits and bobs that deal with being a GraphQL server
 
 use crate::{
-    database::Database,
-    temperature::{Celsius, Fahrenheit},
+    address::BleAddress,
+    assistant::{self, Fit, FitOptions},
+    calibration::{Calibration, CalibrationHistory, DatedCalibration},
+    channels::{Battery, ChannelError, Channels, Humidity, Pressure},
+    database::{
+        Database, DatabaseError, InsertResult, MeasurementResult, NewMeasurement, SeriesBucket,
+    },
+    events::{Broadcaster, EventId, MeasurementEvent},
+    temperature::{
+        self, Celsius, CelsiusDelta, Fahrenheit, Kelvin, OutOfRangeAction, PlausibleRange,
+        TemperatureError, TemperatureUnit,
+    },
 };
 use chrono::prelude::*;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use juniper::FieldResult;
 use std::collections::BTreeMap;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+
+/// How many points a series has, unless more or fewer are asked for.
+const DEFAULT_SERIES_POINTS: i32 = 200;
+
+/// The most points a series can have.
+const MAX_SERIES_POINTS: i32 = 1000;
 
 /// A known device
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Device {
     /// The BLE address of the device
-    pub address: String,
+    pub address: BleAddress,
     /// The human-readable name of the device, if available.
     pub name: Option<String>,
     /// The human-readable description for the device, if available.
     pub description: Option<String>,
-    /// How to adjust the raw readings, in case of a miscalibrated temperature sensor, in degrees
-    /// celsius.
-    pub adjustment: Celsius,
+    /// How to turn the raw readings into actual temperatures, in case of a miscalibrated
+    /// temperature sensor. Each measurement uses the calibration that was in effect when it was
+    /// taken.
+    pub calibrations: CalibrationHistory,
+    /// The temperatures that the device can believably report, if it has limits.
+    pub plausible_range: Option<PlausibleRange>,
 }
 
 /// A device according to our GraphQL layer. The device might be known or unknown.
 #[derive(Clone)]
-enum DeviceRef<'a> {
-    /// A device that we know about because of our sensors.toml config file
-    Known(&'a Device),
-    /// A device that wasn't in our sensors.toml file, but may still have data associated with it.
-    Unknown(String),
+enum DeviceRef {
+    /// A device that we know about because it's in the device registry
+    Known(Device),
+    /// A device that isn't in the device registry, but may still have data associated with it.
+    Unknown(BleAddress),
 }
 
-impl<'a> DeviceRef<'a> {
-    /// How far to adjust the temperatures for this device, in degrees celsius.
-    fn adjustment(&self) -> Celsius {
+impl DeviceRef {
+    /// The BLE address of the device, known or not.
+    fn ble_address(&self) -> &BleAddress {
+        match self {
+            DeviceRef::Known(device) => &device.address,
+            DeviceRef::Unknown(address) => address,
+        }
+    }
+
+    /// Every calibration that this device has had.
+    fn calibrations(&self) -> CalibrationHistory {
         match self {
-            DeviceRef::Known(device) => device.adjustment,
-            DeviceRef::Unknown(_) => 0.0.into(),
+            DeviceRef::Known(device) => device.calibrations.clone(),
+            DeviceRef::Unknown(_) => CalibrationHistory::default(),
         }
     }
+
+    /// The calibration that this device uses now.
+    fn current_calibration(&self) -> Calibration {
+        self.calibrations()
+            .current()
+            .cloned()
+            .unwrap_or_else(Calibration::none)
+    }
 }
 
 #[juniper::object(
     Context = Context,
 )]
-impl<'a> DeviceRef<'a> {
+impl DeviceRef {
     /// The BLE address of the device.
-    fn address(&self) -> String {
-        match self {
-            DeviceRef::Known(device) => device.address.clone(),
-            DeviceRef::Unknown(address) => address.clone(),
-        }
+    fn address(&self) -> BleAddress {
+        self.ble_address().clone()
     }
 
     /// The human-readable name of the device, if available.
     fn name(&self) -> Option<String> {
         match self {
             DeviceRef::Known(device) => device.name.clone(),
-            DeviceRef::Unknown(address) => None,
+            DeviceRef::Unknown(_) => None,
         }
     }
 
@@ -67,24 +99,41 @@ impl<'a> DeviceRef<'a> {
     fn description(&self) -> Option<String> {
         match self {
             DeviceRef::Known(device) => device.description.clone(),
-            DeviceRef::Unknown(address) => None,
+            DeviceRef::Unknown(_) => None,
         }
     }
 
     /// How far to adjust the temperatures for this device, in degrees celsius.
-    fn adjustment(&self) -> Celsius {
-        self.adjustment()
+    #[graphql(deprecated = "Use `calibration`, which can also describe gains and tables")]
+    fn adjustment(&self) -> CelsiusDelta {
+        self.current_calibration()
+            .offset()
+            .unwrap_or_else(|| 0.0.into())
+    }
+
+    /// How the raw temperatures for this device are turned into actual temperatures now.
+    fn calibration(&self) -> Calibration {
+        self.current_calibration()
+    }
+
+    /// Every calibration that this device has had, sorted by when they took effect.
+    fn calibrations(&self) -> Vec<DatedCalibration> {
+        self.calibrations().entries().to_vec()
+    }
+
+    /// The temperatures that this device can believably report, if it has limits.
+    fn plausible_range(&self) -> Option<PlausibleRange> {
+        match self {
+            DeviceRef::Known(device) => device.plausible_range,
+            DeviceRef::Unknown(_) => None,
+        }
     }
 
     /// The current (most recent) measurement for this device.
     fn current_measurement(&self, context: &Context) -> FieldResult<Option<Measurement>> {
-        let address: &str = match self {
-            DeviceRef::Known(ref device) => &device.address,
-            DeviceRef::Unknown(ref address) => address,
-        };
         let measurements = context
             .database
-            .select_measurements_for_device(address, 1)?;
+            .select_measurements_for_device(self.ble_address(), 1)?;
 
         let measurement: Option<Measurement> = measurements
             .into_iter()
@@ -92,8 +141,9 @@ impl<'a> DeviceRef<'a> {
                 |measurement| match (measurement.temperature, measurement.date) {
                     (Some(temperature), Some(date)) => Some(Measurement {
                         device: self.clone(),
-                        date: date,
-                        temperature: temperature.into(),
+                        date,
+                        temperature,
+                        channels: measurement.channels,
                     }),
                     _ => None,
                 },
@@ -105,15 +155,11 @@ impl<'a> DeviceRef<'a> {
 
     /// Measurements for this device.
     fn measurements(&self, context: &Context, count: Option<i32>) -> FieldResult<Vec<Measurement>> {
-        let address: &str = match self {
-            DeviceRef::Known(ref device) => &device.address,
-            DeviceRef::Unknown(ref address) => address,
-        };
         let count = std::cmp::min(count.unwrap_or(10), 100) as u32;
 
         let measurements = context
             .database
-            .select_measurements_for_device(address, count)?;
+            .select_measurements_for_device(self.ble_address(), count)?;
 
         let measurements: Vec<Measurement> = measurements
             .into_iter()
@@ -121,8 +167,9 @@ impl<'a> DeviceRef<'a> {
                 |measurement| match (measurement.temperature, measurement.date) {
                     (Some(temperature), Some(date)) => Some(Measurement {
                         device: self.clone(),
-                        date: date,
-                        temperature: temperature.into(),
+                        date,
+                        temperature,
+                        channels: measurement.channels,
                     }),
                     _ => None,
                 },
@@ -131,25 +178,117 @@ impl<'a> DeviceRef<'a> {
 
         Ok(measurements)
     }
+
+    /// The history of this device between two dates (until now, by default), summarized into
+    /// at most `points` evenly spaced points. Stretches of time with no measurements are left
+    /// out. For devices calibrated with a table, each point's min, mean, and max are approximate.
+    fn series(
+        &self,
+        context: &Context,
+        from: DateTime<Utc>,
+        until: Option<DateTime<Utc>>,
+        points: Option<i32>,
+    ) -> FieldResult<Vec<SeriesBucket>> {
+        let until = until.unwrap_or_else(Utc::now);
+        if until <= from {
+            return Err("A series has to end after it starts".into());
+        }
+        let points = points.unwrap_or(DEFAULT_SERIES_POINTS);
+        if !(1..=MAX_SERIES_POINTS).contains(&points) {
+            return Err(format!(
+                "A series can have between 1 and {} points",
+                MAX_SERIES_POINTS
+            )
+            .into());
+        }
+
+        Ok(context.series(self.ble_address(), from, until, points as u32)?)
+    }
+}
+
+#[juniper::object]
+impl SeriesBucket {
+    /// When the stretch of time starts
+    fn date(&self) -> DateTime<Utc> {
+        self.date
+    }
+
+    /// How many measurements were taken during it
+    fn count(&self) -> i32 {
+        self.count.min(i32::MAX as u64) as i32
+    }
+
+    /// The lowest (adjusted) temperature, in the requested unit (degrees celsius by default)
+    fn min(&self, unit: Option<TemperatureUnit>) -> f64 {
+        unit.unwrap_or(TemperatureUnit::Celsius).value_of(self.min)
+    }
+
+    /// The average (adjusted) temperature, in the requested unit (degrees celsius by default)
+    fn mean(&self, unit: Option<TemperatureUnit>) -> f64 {
+        unit.unwrap_or(TemperatureUnit::Celsius).value_of(self.mean)
+    }
+
+    /// The highest (adjusted) temperature, in the requested unit (degrees celsius by default)
+    fn max(&self, unit: Option<TemperatureUnit>) -> f64 {
+        unit.unwrap_or(TemperatureUnit::Celsius).value_of(self.max)
+    }
 }
 
 /// Data about a measurement.
-struct Measurement<'a> {
-    device: DeviceRef<'a>,
+struct Measurement {
+    device: DeviceRef,
     date: DateTime<Utc>,
     temperature: Celsius,
+    channels: Channels,
 }
 
-impl<'a> Measurement<'a> {
-    /// The adjusted value, based on the device that this measurement belong to.
+impl Measurement {
+    /// Whether the (adjusted) temperature is within the plausible range of the device.
+    fn is_plausible(&self) -> bool {
+        match self.device {
+            DeviceRef::Known(Device {
+                plausible_range: Some(ref range),
+                ..
+            }) => range.contains(self.adjusted_temperature()),
+            _ => true,
+        }
+    }
+
+    /// The adjusted value, based on the device that this measurement belong to and the
+    /// calibration it had when the measurement was taken.
     fn adjusted_temperature(&self) -> Celsius {
-        let adjustment = self.device.adjustment();
-        self.temperature + adjustment
+        match self.device {
+            DeviceRef::Known(ref device) => device.calibrations.apply(self.temperature, self.date),
+            DeviceRef::Unknown(_) => self.temperature,
+        }
+    }
+}
+
+impl From<&Measurement> for MeasurementEvent {
+    /// Describe the measurement the way that we send it to subscribers.
+    fn from(measurement: &Measurement) -> Self {
+        let name = match measurement.device {
+            DeviceRef::Known(ref device) => device.name.clone(),
+            DeviceRef::Unknown(_) => None,
+        };
+        let temperature = measurement.adjusted_temperature();
+
+        MeasurementEvent {
+            address: measurement.device.ble_address().clone(),
+            name,
+            date: measurement.date,
+            temp_c: temperature.value(),
+            temp_f: Fahrenheit::from(temperature).value(),
+            plausible: measurement.is_plausible(),
+            humidity: measurement.channels.humidity.map(f64::from),
+            pressure: measurement.channels.pressure.map(f64::from),
+            battery: measurement.channels.battery.map(f64::from),
+        }
     }
 }
 
 #[juniper::object()]
-impl<'a> Measurement<'a> {
+impl Measurement {
     /// The date and time that the measurement was taken.
     fn date(&self) -> DateTime<Utc> {
         self.date
@@ -165,23 +304,670 @@ impl<'a> Measurement<'a> {
         self.adjusted_temperature().into()
     }
 
+    /// The temperature, in kelvin
+    fn temp_k(&self) -> Kelvin {
+        self.adjusted_temperature().into()
+    }
+
+    /// The temperature, in the requested unit (degrees celsius by default)
+    fn temperature(&self, unit: Option<TemperatureUnit>) -> f64 {
+        unit.unwrap_or(TemperatureUnit::Celsius)
+            .value_of(self.adjusted_temperature())
+    }
+
+    /// Whether the temperature is within the plausible range of the device. Readings outside of
+    /// it are only stored if the device's range says to flag them rather than reject them.
+    fn plausible(&self) -> bool {
+        self.is_plausible()
+    }
+
+    /// The relative humidity, in percent, if the sensor measures it
+    fn humidity(&self) -> Option<Humidity> {
+        self.channels.humidity
+    }
+
+    /// The air pressure, in hectopascals, if the sensor measures it
+    fn pressure(&self) -> Option<Pressure> {
+        self.channels.pressure
+    }
+
+    /// The battery level, in percent, if the sensor reports it
+    fn battery(&self) -> Option<Battery> {
+        self.channels.battery
+    }
+
+    /// The dew point, in the requested unit (degrees celsius by default), if the sensor measures
+    /// humidity
+    fn dew_point(&self, unit: Option<TemperatureUnit>) -> Option<f64> {
+        let dew_point =
+            temperature::dew_point(self.adjusted_temperature(), self.channels.humidity?)?;
+        Some(unit.unwrap_or(TemperatureUnit::Celsius).value_of(dew_point))
+    }
+
+    /// What the temperature feels like with the humidity taken into account, in the requested
+    /// unit (degrees celsius by default), if the sensor measures humidity
+    fn heat_index(&self, unit: Option<TemperatureUnit>) -> Option<f64> {
+        let heat_index =
+            temperature::heat_index(self.adjusted_temperature(), self.channels.humidity?);
+        Some(
+            unit.unwrap_or(TemperatureUnit::Celsius)
+                .value_of(heat_index),
+        )
+    }
+
+    /// How much water vapor the air holds, in grams per cubic meter, if the sensor measures
+    /// humidity
+    fn absolute_humidity(&self) -> Option<f64> {
+        Some(temperature::absolute_humidity(
+            self.adjusted_temperature(),
+            self.channels.humidity?,
+        ))
+    }
+
     /// The raw (unadjusted) sensor temperature
     fn temp_raw_c(&self) -> f64 {
         self.temperature.into()
     }
+
+    /// The raw (unadjusted) sensor temperature, in the requested unit (degrees celsius by
+    /// default)
+    fn raw_temperature(&self, unit: Option<TemperatureUnit>) -> f64 {
+        unit.unwrap_or(TemperatureUnit::Celsius)
+            .value_of(self.temperature)
+    }
+}
+
+#[juniper::object()]
+impl DatedCalibration {
+    /// When the calibration took effect, or null if it has always been in effect.
+    fn effective_from(&self) -> Option<DateTime<Utc>> {
+        self.effective_from
+    }
+
+    /// The calibration itself.
+    fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+}
+
+/// The different kinds of calibrations.
+#[derive(juniper::GraphQLEnum)]
+enum CalibrationKind {
+    /// A fixed number of degrees is added to every reading.
+    Offset,
+    /// Every reading is multiplied by a gain, and then an offset is added.
+    Linear,
+    /// Readings are interpolated between known points.
+    Table,
+}
+
+/// A single point in a calibration table.
+#[derive(juniper::GraphQLObject)]
+struct CalibrationPoint {
+    /// What the sensor read
+    raw: Celsius,
+    /// What the temperature actually was
+    actual: Celsius,
+}
+
+#[juniper::object()]
+impl Calibration {
+    /// What kind of calibration this is.
+    fn kind(&self) -> CalibrationKind {
+        match self {
+            Calibration::Offset(_) => CalibrationKind::Offset,
+            Calibration::Linear { .. } => CalibrationKind::Linear,
+            Calibration::Table(_) => CalibrationKind::Table,
+        }
+    }
+
+    /// How many degrees celsius are added to every reading, for offset and linear calibrations.
+    fn offset(&self) -> Option<CelsiusDelta> {
+        self.offset()
+    }
+
+    /// How much every reading is multiplied by, for linear calibrations.
+    fn gain(&self) -> Option<f64> {
+        match self {
+            Calibration::Linear { gain, .. } => Some(*gain),
+            _ => None,
+        }
+    }
+
+    /// The known points, sorted by the raw reading, for table calibrations.
+    fn points(&self) -> Option<Vec<CalibrationPoint>> {
+        match self {
+            Calibration::Table(points) => Some(
+                points
+                    .iter()
+                    .map(|&(raw, actual)| CalibrationPoint { raw, actual })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// A calibration to store for a device. Give `offset` on its own for a fixed offset, `gain` (and
+/// optionally `offset`) for a linear calibration, or `points` on its own for a table.
+#[derive(juniper::GraphQLInputObject)]
+pub struct CalibrationInput {
+    /// How many degrees celsius to add to every reading
+    offset: Option<CelsiusDelta>,
+    /// How much to multiply every reading by
+    gain: Option<f64>,
+    /// Known (raw, actual) points to interpolate between
+    points: Option<Vec<CalibrationPointInput>>,
+}
+
+/// A single point in a calibration table.
+#[derive(juniper::GraphQLInputObject)]
+pub struct CalibrationPointInput {
+    /// What the sensor read
+    raw: Celsius,
+    /// What the temperature actually was
+    actual: Celsius,
+}
+
+impl CalibrationInput {
+    /// Turn the input into a calibration, making sure that it makes sense and is believable.
+    fn into_calibration(self) -> FieldResult<Calibration> {
+        let calibration = match (self.offset, self.gain, self.points) {
+            (None, None, Some(points)) => Calibration::table(
+                points
+                    .into_iter()
+                    .map(|point| (point.raw, point.actual))
+                    .collect(),
+            )?,
+            (_, _, Some(_)) => {
+                return Err("A calibration table can't also have an offset or a gain".into())
+            }
+            (offset, Some(gain), None) => {
+                Calibration::linear(gain, offset.unwrap_or_else(|| 0.0.into()))?
+            }
+            (Some(offset), None, None) => Calibration::adjustment(offset)?,
+            (None, None, None) => {
+                return Err("A calibration needs an offset, a gain, or points".into())
+            }
+        };
+        calibration.check_believable()?;
+
+        Ok(calibration)
+    }
+}
+
+/// A calibration fitted by the calibration assistant, and how well it fits.
+struct CalibrationFit {
+    address: BleAddress,
+    reference: BleAddress,
+    fit: Fit,
+}
+
+#[juniper::object()]
+impl CalibrationFit {
+    /// The calibration that brings the device's readings closest to the reference's.
+    fn calibration(&self) -> &Calibration {
+        &self.fit.calibration
+    }
+
+    /// How many pairs of readings the calibration was fitted to.
+    fn pairs(&self) -> i32 {
+        self.fit.pairs as i32
+    }
+
+    /// The root-mean-square difference between the calibrated and reference readings, in degrees
+    /// celsius.
+    fn rms_error(&self) -> CelsiusDelta {
+        self.fit.rms_error
+    }
+
+    /// The largest difference between a calibrated and a reference reading, in degrees celsius.
+    fn max_error(&self) -> CelsiusDelta {
+        self.fit.max_error
+    }
+
+    /// How much of the variation in the reference readings the calibration explains, from 0 to
+    /// 1. Null if the reference readings don't vary at all.
+    fn r_squared(&self) -> Option<f64> {
+        self.fit.r_squared
+    }
+
+    /// The calibration, written as a sensor for sensors.toml.
+    fn sensors_toml(&self) -> String {
+        self.fit.sensors_toml(&self.address, &self.reference)
+    }
+}
+
+/// The temperatures that a device can believably report.
+#[derive(juniper::GraphQLInputObject)]
+pub struct PlausibleRangeInput {
+    /// The coldest believable temperature
+    min: Option<Celsius>,
+    /// The warmest believable temperature
+    max: Option<Celsius>,
+    /// What to do with readings outside of the range (rejecting them, by default)
+    action: Option<OutOfRangeAction>,
+}
+
+impl PlausibleRangeInput {
+    /// Turn the input into a plausible range (or no range at all, if it has no limits), making
+    /// sure that it makes sense.
+    fn into_plausible_range(self) -> FieldResult<Option<PlausibleRange>> {
+        if let Some(min) = self.min {
+            min.validate()?;
+        }
+        if let Some(max) = self.max {
+            max.validate()?;
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min.value() > max.value() {
+                return Err("A plausible range's min can't be above its max".into());
+            }
+        }
+
+        Ok(match (self.min, self.max) {
+            (None, None) => None,
+            (min, max) => Some(PlausibleRange {
+                min,
+                max,
+                action: self.action.unwrap_or_default(),
+            }),
+        })
+    }
 }
 
 /// Context that is passed to GraphQL queries
 pub struct Context {
     /// The ElasticSearch database
     pub database: Arc<Database>,
-    /// A list of devices
-    pub devices: Arc<BTreeMap<String, Device>>,
+    /// The device registry, keyed by address. This is kept in step with the devices stored in
+    /// the database, so we don't have to go to the database to look up a device.
+    pub devices: Arc<RwLock<BTreeMap<BleAddress, Device>>>,
+    /// Where accepted measurements are announced
+    pub events: Arc<Broadcaster>,
+    /// The unit that temperatures should be shown in: the server's default, unless the request
+    /// asked for another one
+    pub unit: TemperatureUnit,
+}
+
+impl Context {
+    /// Look up a device by address, whether we know about it or not.
+    fn device_ref(&self, address: BleAddress) -> DeviceRef {
+        match self.devices.read().unwrap().get(&address) {
+            Some(device) => DeviceRef::Known(device.clone()),
+            None => DeviceRef::Unknown(address),
+        }
+    }
+
+    /// Summarize a device's history between two dates into at most `points` evenly spaced
+    /// buckets, with the device's calibrations applied.
+    ///
+    /// Each bucket is summarized separately on either side of a recalibration, so every reading
+    /// gets the calibration that was in effect when it was taken. Offsets and linear calibrations
+    /// apply exactly to a bucket's lowest, average, and highest readings, but a calibration table
+    /// bends between its points, so for tables those are only approximate.
+    pub fn series(
+        &self,
+        address: &BleAddress,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+        points: u32,
+    ) -> Result<Vec<SeriesBucket>, DatabaseError> {
+        // Round the interval up to a whole second, so that we never go over the number of points.
+        let points = i64::from(points.max(1));
+        let seconds = (until - from).num_seconds();
+        let interval = Duration::seconds((seconds + points - 1) / points);
+
+        let calibrations = self.device_ref(address.clone()).calibrations();
+        let mut edges = vec![from];
+        edges.extend(
+            calibrations
+                .entries()
+                .iter()
+                .filter_map(|calibration| calibration.effective_from)
+                .filter(|&effective_from| from < effective_from && effective_from < until),
+        );
+        edges.push(until);
+
+        let mut series: BTreeMap<DateTime<Utc>, SeriesBucket> = BTreeMap::new();
+        for part in edges.windows(2) {
+            let buckets = self
+                .database
+                .select_series(address, from, part[0], part[1], interval)?;
+
+            for bucket in buckets {
+                let bucket = calibrate_bucket(&calibrations, part[0], bucket);
+                let bucket = match series.remove(&bucket.date) {
+                    Some(earlier) => merge_buckets(earlier, bucket),
+                    None => bucket,
+                };
+                series.insert(bucket.date, bucket);
+            }
+        }
+
+        Ok(series.into_values().collect())
+    }
+
+    /// Check a raw reading from a device, store it, and announce it to subscribers. This is what
+    /// `addMeasurement` does, for the places that take measurements without going through
+    /// GraphQL.
+    pub fn add_measurement(
+        &self,
+        address: BleAddress,
+        date: DateTime<Utc>,
+        temperature: Celsius,
+        channels: Channels,
+    ) -> Result<MeasurementEvent, MeasurementError> {
+        let measurement = self.store_measurement(address, date, temperature, channels)?;
+        Ok(MeasurementEvent::from(&measurement))
+    }
+
+    /// Check a batch of raw readings, store the ones that pass in a single request, and announce
+    /// the ones that are newly stored to subscribers. This is for the places that take many
+    /// measurements at once and might send them again if something goes wrong.
+    ///
+    /// Each reading is checked the same way [`Context::add_measurement`] checks it, and gets its
+    /// own result, in order. Unlike `add_measurement`, readings never replace one that's already
+    /// stored for the same device and second; they come back as [`InsertResult::Duplicate`]
+    /// without being announced again, so sending the same batch twice, like a client retrying a
+    /// request that partly failed, is harmless. Only a failed request to the database fails the
+    /// whole batch.
+    pub fn add_measurements(
+        &self,
+        measurements: Vec<NewMeasurement>,
+    ) -> Result<Vec<Result<InsertResult, MeasurementError>>, DatabaseError> {
+        let mut results = Vec::with_capacity(measurements.len());
+        let mut checked = Vec::new();
+        for measurement in measurements {
+            match self.check_measurement(
+                measurement.address,
+                measurement.date,
+                measurement.temperature,
+                measurement.channels,
+            ) {
+                Ok(measurement) => {
+                    results.push(Ok(InsertResult::Inserted));
+                    checked.push(measurement);
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+
+        let new_measurements: Vec<NewMeasurement> = checked
+            .iter()
+            .map(|measurement| NewMeasurement {
+                address: measurement.device.ble_address().clone(),
+                date: measurement.date,
+                temperature: measurement.temperature,
+                channels: measurement.channels,
+            })
+            .collect();
+        let inserted = self.database.insert_measurements(&new_measurements)?;
+
+        // Fill in what happened to each of the readings that passed, in the same order.
+        let mut inserted = checked.iter().zip(inserted);
+        for result in results.iter_mut().filter(|result| result.is_ok()) {
+            let (measurement, insert_result) = match inserted.next() {
+                Some(next) => next,
+                None => break,
+            };
+            if insert_result == InsertResult::Inserted {
+                self.events.publish(MeasurementEvent::from(measurement));
+            }
+            *result = Ok(insert_result);
+        }
+
+        Ok(results)
+    }
+
+    /// Check a raw reading from a device, store it, and announce it to subscribers.
+    fn store_measurement(
+        &self,
+        address: BleAddress,
+        date: DateTime<Utc>,
+        temperature: Celsius,
+        channels: Channels,
+    ) -> Result<Measurement, MeasurementError> {
+        let measurement = self.check_measurement(address, date, temperature, channels)?;
+
+        self.database
+            .insert_measurement(
+                measurement.device.ble_address(),
+                measurement.date,
+                measurement.temperature,
+                &measurement.channels,
+            )
+            .map_err(MeasurementError::Database)?;
+
+        self.events.publish(MeasurementEvent::from(&measurement));
+
+        Ok(measurement)
+    }
+
+    /// Check that a raw reading from a device can be stored: that the readings can exist, and
+    /// that the temperature is plausible for a device that rejects implausible ones.
+    fn check_measurement(
+        &self,
+        address: BleAddress,
+        date: DateTime<Utc>,
+        temperature: Celsius,
+        channels: Channels,
+    ) -> Result<Measurement, MeasurementError> {
+        let temperature = temperature
+            .validate()
+            .map_err(MeasurementError::Temperature)?;
+        let channels = channels.validate().map_err(MeasurementError::Channel)?;
+        let date = date.with_nanosecond(0).unwrap();
+
+        let measurement = Measurement {
+            device: self.device_ref(address),
+            date,
+            temperature,
+            channels,
+        };
+        if let DeviceRef::Known(Device {
+            plausible_range: Some(ref range),
+            ..
+        }) = measurement.device
+        {
+            if range.action == OutOfRangeAction::Reject && !measurement.is_plausible() {
+                return Err(MeasurementError::Implausible {
+                    temperature: measurement.adjusted_temperature(),
+                    address: measurement.device.ble_address().clone(),
+                    range: *range,
+                });
+            }
+        }
+
+        Ok(measurement)
+    }
+
+    /// Get the most recent measurement of every device in the registry, as events, with a single
+    /// request to the database (which is made even when the registry is empty). Devices that
+    /// haven't reported yet are left out.
+    pub fn current_measurement_events(&self) -> Result<Vec<MeasurementEvent>, DatabaseError> {
+        let devices = self.devices.read().unwrap().clone();
+        let addresses: Vec<BleAddress> = devices.keys().cloned().collect();
+
+        let mut events = Vec::new();
+        for measurement in self.database.select_latest_measurements(&addresses)? {
+            if let MeasurementResult {
+                address: Some(address),
+                date: Some(date),
+                temperature: Some(temperature),
+                channels,
+            } = measurement
+            {
+                let device = match devices.get(&address) {
+                    Some(device) => device.clone(),
+                    None => continue,
+                };
+                let measurement = Measurement {
+                    device: DeviceRef::Known(device),
+                    date,
+                    temperature,
+                    channels,
+                };
+                events.push(MeasurementEvent::from(&measurement));
+            }
+        }
+        events.sort_by(|a, b| a.address.cmp(&b.address));
+
+        Ok(events)
+    }
+
+    /// Get every stored measurement that comes after the event with the specified ID, as events,
+    /// so that a subscriber that reconnects can catch up on what it missed. The measurements are
+    /// read `page_size` at a time, so a long gap takes several requests, but none of it is lost.
+    pub fn measurement_events_after(
+        &self,
+        address: Option<&BleAddress>,
+        after: &EventId,
+        page_size: u32,
+    ) -> Result<Vec<MeasurementEvent>, DatabaseError> {
+        all_pages(after, page_size, |after| {
+            let measurements = self
+                .database
+                .select_measurements_after(address, after, page_size)?;
+
+            Ok(measurements
+                .into_iter()
+                .filter_map(|measurement| {
+                    match (
+                        measurement.address,
+                        measurement.temperature,
+                        measurement.date,
+                    ) {
+                        (Some(address), Some(temperature), Some(date)) => {
+                            let measurement = Measurement {
+                                device: self.device_ref(address),
+                                date,
+                                temperature,
+                                channels: measurement.channels,
+                            };
+                            Some(MeasurementEvent::from(&measurement))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect())
+        })
+    }
+}
+
+/// Read events a page at a time, starting after `after` and asking for the page after the last
+/// event each time, until a page comes back short.
+fn all_pages<E>(
+    after: &EventId,
+    page_size: u32,
+    mut page: impl FnMut(&EventId) -> Result<Vec<MeasurementEvent>, E>,
+) -> Result<Vec<MeasurementEvent>, E> {
+    let mut events = Vec::new();
+    let mut after = after.clone();
+    loop {
+        let next = page(&after)?;
+        let done = next.len() < page_size as usize;
+        match next.last() {
+            Some(last) => after = last.id(),
+            None => return Ok(events),
+        }
+        events.extend(next);
+        if done {
+            return Ok(events);
+        }
+    }
+}
+
+/// Apply the calibration that was in effect on a date to a bucket of raw readings taken from then
+/// on. A calibration table that slopes the wrong way can swap the lowest and highest readings, so
+/// they're put back in order, with the average kept between them.
+fn calibrate_bucket(
+    calibrations: &CalibrationHistory,
+    date: DateTime<Utc>,
+    bucket: SeriesBucket,
+) -> SeriesBucket {
+    let min = calibrations.apply(bucket.min, date).value();
+    let max = calibrations.apply(bucket.max, date).value();
+    let (min, max) = (min.min(max), min.max(max));
+    let mean = calibrations
+        .apply(bucket.mean, date)
+        .value()
+        .max(min)
+        .min(max);
+
+    SeriesBucket {
+        min: min.into(),
+        mean: mean.into(),
+        max: max.into(),
+        ..bucket
+    }
+}
+
+/// Combine the parts of a bucket from either side of a recalibration.
+fn merge_buckets(a: SeriesBucket, b: SeriesBucket) -> SeriesBucket {
+    let count = a.count + b.count;
+    let mean = (a.mean.value() * a.count as f64 + b.mean.value() * b.count as f64) / count as f64;
+
+    SeriesBucket {
+        date: a.date,
+        count,
+        min: a.min.value().min(b.min.value()).into(),
+        mean: mean.into(),
+        max: a.max.value().max(b.max.value()).into(),
+    }
+}
+
+/// Reasons that a measurement isn't stored.
+#[derive(Debug)]
+pub enum MeasurementError {
+    /// The temperature can't exist.
+    Temperature(TemperatureError),
+    /// One of the other readings can't exist.
+    Channel(ChannelError),
+    /// The temperature is outside of the device's plausible range, and the device rejects those.
+    Implausible {
+        /// The temperature, with the device's calibration applied
+        temperature: Celsius,
+        /// The device that took the measurement
+        address: BleAddress,
+        /// The device's plausible range
+        range: PlausibleRange,
+    },
+    /// The measurement couldn't be stored.
+    Database(DatabaseError),
+}
+
+impl std::fmt::Display for MeasurementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            MeasurementError::Temperature(e) => e.fmt(f),
+            MeasurementError::Channel(e) => e.fmt(f),
+            MeasurementError::Implausible {
+                temperature,
+                address,
+                range,
+            } => write!(
+                f,
+                "{:.2} is outside of the plausible range for {} ({})",
+                temperature, address, range
+            ),
+            MeasurementError::Database(e) => e.fmt(f),
+        }
+    }
 }
 
 // To make our context usable by Juniper, we have to implement a marker trait.
 impl juniper::Context for Context {}
 
+/// How the server would like its data shown.
+#[derive(juniper::GraphQLObject)]
+struct Settings {
+    /// The unit that temperatures should be shown in
+    unit: TemperatureUnit,
+    /// The symbol for the unit, like °F
+    unit_symbol: String,
+}
+
 /// The GraphQL object that represents the base Query interface.
 pub struct Query;
 
@@ -189,13 +975,76 @@ pub struct Query;
     Context = Context,
 )]
 impl Query {
-    pub fn device(context: &Context, address: String) -> FieldResult<DeviceRef> {
-        let device: DeviceRef = match context.devices.get(&address) {
-            Some(device) => DeviceRef::Known(device),
-            None => DeviceRef::Unknown(address),
+    pub fn device(context: &Context, address: BleAddress) -> FieldResult<DeviceRef> {
+        Ok(context.device_ref(address))
+    }
+
+    /// How the server would like its data shown. The unit is the server's default, unless the
+    /// request overrode it with an `X-Temperature-Unit` header.
+    pub fn settings(context: &Context) -> Settings {
+        Settings {
+            unit: context.unit,
+            unit_symbol: context.unit.to_string(),
+        }
+    }
+
+    /// All of the devices in the device registry.
+    pub fn devices(context: &Context) -> FieldResult<Vec<DeviceRef>> {
+        let devices = context
+            .devices
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .map(DeviceRef::Known)
+            .collect();
+
+        Ok(devices)
+    }
+
+    /// Work out how to calibrate a device, by comparing its raw readings to the readings of a
+    /// trusted reference device taken at about the same time. Fits an offset, or a gain and an
+    /// offset if `fitGain` is set.
+    ///
+    /// Readings are paired up if they're no more than `toleranceSeconds` apart (5 minutes by
+    /// default), and only readings between `from` and `until` (now, by default) are used.
+    pub fn calibrationFit(
+        context: &Context,
+        address: BleAddress,
+        reference: BleAddress,
+        from: DateTime<Utc>,
+        until: Option<DateTime<Utc>>,
+        fit_gain: Option<bool>,
+        tolerance_seconds: Option<i32>,
+    ) -> FieldResult<CalibrationFit> {
+        if tolerance_seconds.is_some_and(|tolerance_seconds| tolerance_seconds <= 0) {
+            return Err("toleranceSeconds must be more than zero".into());
+        }
+        let options = FitOptions {
+            from,
+            until: until.unwrap_or_else(Utc::now),
+            tolerance: chrono::Duration::seconds(
+                tolerance_seconds
+                    .map(i64::from)
+                    .unwrap_or(assistant::DEFAULT_TOLERANCE_SECONDS),
+            ),
+            fit_gain: fit_gain.unwrap_or(false),
         };
+        let reference_calibrations = context.device_ref(reference.clone()).calibrations();
+
+        let fit = assistant::fit_against_reference(
+            &context.database,
+            &address,
+            &reference,
+            &reference_calibrations,
+            &options,
+        )?;
 
-        Ok(device)
+        Ok(CalibrationFit {
+            address,
+            reference,
+            fit,
+        })
     }
 }
 
@@ -208,28 +1057,142 @@ pub struct Mutation;
     Context = Context,
 )]
 impl Mutation {
+    /// Store a raw reading from a device. Give the reading either as `tempC`, or as
+    /// `temperature` in the specified `unit` (degrees celsius by default).
     pub fn addMeasurement(
         context: &Context,
-        address: String,
-        temp_c: Celsius,
+        address: BleAddress,
+        temp_c: Option<Celsius>,
+        temperature: Option<f64>,
+        unit: Option<TemperatureUnit>,
+        humidity: Option<Humidity>,
+        pressure: Option<Pressure>,
+        battery: Option<Battery>,
         date: Option<DateTime<Utc>>,
     ) -> FieldResult<Measurement> {
-        let date = date.unwrap_or(Utc::now()).with_nanosecond(0).unwrap();
+        let temp_c = match (temp_c, temperature) {
+            (Some(temp_c), None) if unit.is_none() => temp_c,
+            (None, Some(temperature)) => unit
+                .unwrap_or(TemperatureUnit::Celsius)
+                .to_celsius(temperature),
+            (Some(_), _) => {
+                return Err("Give the reading as either tempC, or temperature and unit".into())
+            }
+            (None, None) => return Err("A measurement needs tempC or temperature".into()),
+        };
+        let channels = Channels {
+            humidity,
+            pressure,
+            battery,
+        };
 
-        context
-            .database
-            .insert_measurement(&address, date, temp_c)?;
+        Ok(context.store_measurement(address, date.unwrap_or_else(Utc::now), temp_c, channels)?)
+    }
 
-        let device: DeviceRef = match context.devices.get(&address) {
-            Some(ref device) => DeviceRef::Known(device),
-            None => DeviceRef::Unknown(address),
+    /// Add a device to the device registry, or update it if it's already there. Any fields that
+    /// are left out keep their current value (or are empty, for a new device).
+    ///
+    /// `adjustment` is a shorthand for a calibration with only an offset, so only one of
+    /// `adjustment` and `calibration` can be given. The calibration is added to the device's
+    /// calibration history and only applies to measurements taken from `effectiveFrom` on
+    /// (replacing a calibration that took effect at the same time). `effectiveFrom` defaults to
+    /// now, so that recalibrating a device doesn't change what its old measurements mean, unless
+    /// the device has never been calibrated, in which case the calibration applies to every
+    /// measurement.
+    ///
+    /// A `plausibleRange` with neither a `min` nor a `max` removes the device's plausible range.
+    pub fn upsertDevice(
+        context: &Context,
+        address: BleAddress,
+        name: Option<String>,
+        description: Option<String>,
+        adjustment: Option<CelsiusDelta>,
+        calibration: Option<CalibrationInput>,
+        effective_from: Option<DateTime<Utc>>,
+        plausible_range: Option<PlausibleRangeInput>,
+    ) -> FieldResult<DeviceRef> {
+        let plausible_range = match plausible_range {
+            Some(plausible_range) => Some(plausible_range.into_plausible_range()?),
+            None => None,
         };
+        let calibration = match (adjustment, calibration) {
+            (Some(_), Some(_)) => {
+                return Err("Only one of adjustment and calibration can be given".into())
+            }
+            (Some(adjustment), None) => {
+                let calibration = Calibration::adjustment(adjustment)?;
+                calibration.check_believable()?;
+                Some(calibration)
+            }
+            (None, Some(calibration)) => Some(calibration.into_calibration()?),
+            (None, None) => None,
+        };
+        if calibration.is_none() && effective_from.is_some() {
+            return Err("effectiveFrom needs an adjustment or a calibration".into());
+        }
 
-        Ok(Measurement {
-            device: device,
-            date: date,
-            temperature: temp_c,
-        })
+        let existing = context.devices.read().unwrap().get(&address).cloned();
+        let (name, description, mut calibrations, plausible_range) = match existing {
+            Some(existing) => (
+                name.or(existing.name),
+                description.or(existing.description),
+                existing.calibrations,
+                plausible_range.unwrap_or(existing.plausible_range),
+            ),
+            None => (
+                name,
+                description,
+                CalibrationHistory::default(),
+                plausible_range.unwrap_or(None),
+            ),
+        };
+        if let Some(calibration) = calibration {
+            let effective_from = match effective_from {
+                Some(effective_from) => Some(effective_from),
+                None if calibrations.entries().is_empty() => None,
+                // Measurements are only stored to the second, so calibrations start on one.
+                None => Some(Utc::now().trunc_subsecs(0)),
+            };
+            calibrations.insert(DatedCalibration {
+                effective_from,
+                calibration,
+            });
+        }
+        let device = Device {
+            address,
+            name,
+            description,
+            calibrations,
+            plausible_range,
+        };
+
+        context.database.upsert_device(
+            &device.address,
+            device.name.as_deref(),
+            device.description.as_deref(),
+            &device.calibrations,
+            device.plausible_range.as_ref(),
+        )?;
+
+        context
+            .devices
+            .write()
+            .unwrap()
+            .insert(device.address.clone(), device.clone());
+
+        Ok(DeviceRef::Known(device))
+    }
+
+    /// Remove a device from the device registry. Its measurements are kept, and it will show up
+    /// as an unknown device from now on. Returns whether the device was in the registry.
+    ///
+    /// Note that a device that is listed in sensors.toml will be seeded again the next time the
+    /// server starts.
+    pub fn removeDevice(context: &Context, address: BleAddress) -> FieldResult<bool> {
+        let existed = context.database.delete_device(&address)?;
+        let known = context.devices.write().unwrap().remove(&address).is_some();
+
+        Ok(existed || known)
     }
 }
 
@@ -242,3 +1205,78 @@ pub type Schema = juniper::RootNode<'static, Query, Mutation>;
 pub fn schema() -> Schema {
     Schema::new(Query, Mutation)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(count: u64, min: f64, mean: f64, max: f64) -> SeriesBucket {
+        SeriesBucket {
+            date: Utc.timestamp_opt(1_572_609_600, 0).unwrap(),
+            count,
+            min: min.into(),
+            mean: mean.into(),
+            max: max.into(),
+        }
+    }
+
+    #[test]
+    fn calibrated_buckets_stay_in_order() {
+        // A table that reads backwards swaps the lowest and highest readings.
+        let table =
+            Calibration::table(vec![(10.0.into(), 30.0.into()), (30.0.into(), 10.0.into())])
+                .unwrap();
+        let calibrations = CalibrationHistory::from(table);
+        let date = Utc.timestamp_opt(1_572_609_600, 0).unwrap();
+
+        let calibrated = calibrate_bucket(&calibrations, date, bucket(3, 12.0, 20.0, 28.0));
+        assert_eq!(calibrated.min.value(), 12.0);
+        assert_eq!(calibrated.mean.value(), 20.0);
+        assert_eq!(calibrated.max.value(), 28.0);
+    }
+
+    #[test]
+    fn replays_every_page() {
+        let address: BleAddress = "f4d55889b1d6".parse().unwrap();
+        let stored: Vec<MeasurementEvent> = (0..7)
+            .map(|second| MeasurementEvent {
+                address: address.clone(),
+                name: None,
+                date: Utc.timestamp_opt(1_572_609_600 + second, 0).unwrap(),
+                temp_c: 20.0,
+                temp_f: 68.0,
+                plausible: true,
+                humidity: None,
+                pressure: None,
+                battery: None,
+            })
+            .collect();
+
+        let mut pages = 0;
+        let replayed = all_pages::<()>(&stored[0].id(), 3, |after| {
+            pages += 1;
+            Ok(stored
+                .iter()
+                .filter(|event| event.id() > *after)
+                .take(3)
+                .cloned()
+                .collect())
+        })
+        .unwrap();
+
+        let ids: Vec<EventId> = replayed.iter().map(MeasurementEvent::id).collect();
+        let expected: Vec<EventId> = stored[1..].iter().map(MeasurementEvent::id).collect();
+        assert_eq!(ids, expected);
+        // Two full pages, and an empty one to find out that there's nothing more.
+        assert_eq!(pages, 3);
+    }
+
+    #[test]
+    fn merged_buckets() {
+        let merged = merge_buckets(bucket(1, 20.0, 20.0, 20.0), bucket(3, 8.0, 10.0, 12.0));
+        assert_eq!(merged.count, 4);
+        assert_eq!(merged.min.value(), 8.0);
+        assert_eq!(merged.mean.value(), 12.5);
+        assert_eq!(merged.max.value(), 20.0);
+    }
+}
@@ -0,0 +1,729 @@
+//! Loading measurement history from CSV files
+//!
+//! Readings from before this system existed can be loaded from CSV files with an address, a
+//! timestamp, and a temperature on each row. Which columns those are, and which unit the
+//! temperatures are in, is up to the [`ImportOptions`]. Rows are checked the same way that new
+//! measurements are (including against the plausible range of devices that reject readings
+//! outside of it), and stored in batches with [`Database::insert_measurements`], so rows that are
+//! already stored (or that appear twice) are reported as duplicates instead of being stored again.
+//!
+//! ```
+//! # use temperature_app::import::{CsvRecords, ImportOptions};
+//! # use temperature_app::temperature::TemperatureUnit;
+//! let csv = "sensor,when,temp_f\n\
+//!            f4d55889b1d6,2019-11-01 12:00:00,68\n\
+//!            f4d55889b1d6,yesterday,68\n";
+//! let options = ImportOptions {
+//!     address: "sensor".parse().unwrap(),
+//!     timestamp: "when".parse().unwrap(),
+//!     temperature: "temp_f".parse().unwrap(),
+//!     unit: TemperatureUnit::Fahrenheit,
+//!     ..ImportOptions::default()
+//! };
+//!
+//! let mut records = CsvRecords::new(csv.as_bytes());
+//! let (_, header) = records.next().unwrap().unwrap();
+//! let columns = options.columns(Some(&header)).unwrap();
+//!
+//! let (_, record) = records.next().unwrap().unwrap();
+//! let measurement = columns.measurement(&record).unwrap();
+//! assert_eq!(measurement.temperature.value(), 20.0);
+//! assert_eq!(measurement.date.to_rfc3339(), "2019-11-01T12:00:00+00:00");
+//!
+//! let (line, record) = records.next().unwrap().unwrap();
+//! let error = columns.measurement(&record).unwrap_err();
+//! assert_eq!(line, 3);
+//! assert_eq!(error.to_string(), "\"yesterday\" is not a timestamp");
+//! ```
+
+use crate::address::BleAddress;
+use crate::channels::Channels;
+use crate::database::{Database, DatabaseError, InsertResult, NewMeasurement};
+use crate::graphql::Device;
+use crate::temperature::{
+    Celsius, OutOfRangeAction, PlausibleRange, TemperatureError, TemperatureParseError,
+    TemperatureUnit,
+};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::BufRead;
+
+/// How many rows to store in each request to the database.
+const BATCH_SIZE: usize = 1000;
+
+/// The most lines that a single record can span. A quoted field that goes on for longer than this
+/// is far more likely to be a stray quote than a very long note.
+const MAX_RECORD_LINES: usize = 100;
+
+/// A column of a CSV file: either the name in its header row, or its number, counting from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    /// The column with this name in the header row
+    Name(String),
+    /// The column at this position, counting from 1
+    Number(usize),
+}
+
+impl std::str::FromStr for Column {
+    type Err = ImportError;
+
+    /// Parse a column, which is a number if it's made of digits and a name otherwise.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.parse::<usize>() {
+            Ok(0) => Err(ImportError::InvalidColumn(s.into())),
+            Ok(number) => Ok(Column::Number(number)),
+            Err(_) if s.is_empty() => Err(ImportError::InvalidColumn(s.into())),
+            Err(_) => Ok(Column::Name(s.into())),
+        }
+    }
+}
+
+impl std::fmt::Display for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Column::Name(name) => write!(f, "the \"{}\" column", name),
+            Column::Number(number) => write!(f, "column {}", number),
+        }
+    }
+}
+
+/// How to read a CSV file of measurements.
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// The column with the device's BLE address
+    pub address: Column,
+    /// The column with the time the reading was taken. Timestamps can be RFC 3339, like
+    /// `2019-11-01T12:00:00-05:00`, a date and time in UTC, like `2019-11-01 17:00:00`, or a
+    /// number of seconds since the UNIX epoch.
+    pub timestamp: Column,
+    /// The column with the temperature
+    pub temperature: Column,
+    /// The unit of temperatures that don't say what unit they're in
+    pub unit: TemperatureUnit,
+    /// Whether the first row is a header row, rather than a measurement
+    pub header: bool,
+}
+
+impl Default for ImportOptions {
+    /// Columns named `address`, `timestamp`, and `temperature`, in degrees celsius.
+    fn default() -> Self {
+        ImportOptions {
+            address: Column::Name("address".into()),
+            timestamp: Column::Name("timestamp".into()),
+            temperature: Column::Name("temperature".into()),
+            unit: TemperatureUnit::Celsius,
+            header: true,
+        }
+    }
+}
+
+/// Errors that stop a whole import.
+#[derive(Debug)]
+pub enum ImportError {
+    /// A column was given as neither a name nor a number from 1 up.
+    InvalidColumn(String),
+    /// A column was given by name, but there's no header row, or it isn't in the header row.
+    MissingColumn(Column),
+    /// The file couldn't be read.
+    Io(std::io::Error),
+    /// The header row has a quoted field that's never closed.
+    UnterminatedHeader,
+    /// The measurements couldn't be stored.
+    Database(DatabaseError),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ImportError::InvalidColumn(column) => write!(
+                f,
+                "\"{}\" is not a column. Use the column's name or its number, starting from 1",
+                column
+            ),
+            ImportError::MissingColumn(column) => {
+                write!(f, "The file doesn't have {}", column)
+            }
+            ImportError::Io(e) => write!(f, "Could not read the file: {}", e),
+            ImportError::UnterminatedHeader => {
+                write!(f, "The header row has a quoted field that's never closed")
+            }
+            ImportError::Database(e) => write!(f, "Could not store the measurements: {}", e),
+        }
+    }
+}
+
+/// Reasons that a single row is skipped.
+#[derive(Debug)]
+pub enum RowError {
+    /// The row doesn't have one of the columns.
+    MissingField(Column),
+    /// The address isn't a BLE address.
+    InvalidAddress(String),
+    /// The timestamp isn't in any of the formats we understand.
+    InvalidTimestamp(String),
+    /// The temperature isn't a temperature.
+    InvalidTemperature(TemperatureParseError),
+    /// The temperature can't exist.
+    ImpossibleTemperature(TemperatureError),
+    /// The calibrated temperature is outside of the plausible range of a device that rejects
+    /// readings outside of it.
+    Implausible(Celsius, PlausibleRange),
+    /// A quoted field starts in the row, but is never closed.
+    UnterminatedQuote,
+    /// ElasticSearch refused the measurement.
+    Rejected(String),
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            RowError::MissingField(column) => write!(f, "The row doesn't have {}", column),
+            RowError::InvalidAddress(address) => {
+                write!(f, "\"{}\" is not a BLE address", address)
+            }
+            RowError::InvalidTimestamp(timestamp) => {
+                write!(f, "\"{}\" is not a timestamp", timestamp)
+            }
+            RowError::InvalidTemperature(e) => e.fmt(f),
+            RowError::ImpossibleTemperature(e) => e.fmt(f),
+            RowError::Implausible(temperature, range) => write!(
+                f,
+                "{:.2} is outside of the plausible range for the device ({})",
+                temperature, range
+            ),
+            RowError::UnterminatedQuote => write!(
+                f,
+                "A quoted field starts in this row, but isn't closed within {} lines or by the \
+                 end of the file",
+                MAX_RECORD_LINES
+            ),
+            RowError::Rejected(reason) => write!(f, "The database refused it: {}", reason),
+        }
+    }
+}
+
+/// Which position each of the columns is at, once the header row has been read.
+#[derive(Debug, Clone)]
+pub struct Columns {
+    address: (Column, usize),
+    timestamp: (Column, usize),
+    temperature: (Column, usize),
+    unit: TemperatureUnit,
+}
+
+impl ImportOptions {
+    /// Find the columns, by looking up their names in the header row if they have them.
+    pub fn columns(&self, header: Option<&[String]>) -> Result<Columns, ImportError> {
+        let find = |column: &Column| match column {
+            Column::Number(number) => Ok((column.clone(), number - 1)),
+            Column::Name(name) => header
+                .and_then(|header| header.iter().position(|field| field.trim() == name))
+                .map(|index| (column.clone(), index))
+                .ok_or_else(|| ImportError::MissingColumn(column.clone())),
+        };
+
+        Ok(Columns {
+            address: find(&self.address)?,
+            timestamp: find(&self.timestamp)?,
+            temperature: find(&self.temperature)?,
+            unit: self.unit,
+        })
+    }
+}
+
+impl Columns {
+    /// Read a measurement from a row, and make sure it's one that could have been taken.
+    pub fn measurement(&self, record: &[String]) -> Result<NewMeasurement, RowError> {
+        let field = |(column, index): &(Column, usize)| {
+            record
+                .get(*index)
+                .map(|field| field.trim())
+                .ok_or_else(|| RowError::MissingField(column.clone()))
+        };
+
+        let address = field(&self.address)?;
+        let address: BleAddress = address
+            .parse()
+            .map_err(|_| RowError::InvalidAddress(address.into()))?;
+        let timestamp = field(&self.timestamp)?;
+        let date = parse_timestamp(timestamp)
+            .ok_or_else(|| RowError::InvalidTimestamp(timestamp.into()))?;
+        let temperature = self
+            .unit
+            .parse_celsius(field(&self.temperature)?)
+            .map_err(RowError::InvalidTemperature)?
+            .validate()
+            .map_err(RowError::ImpossibleTemperature)?;
+
+        Ok(NewMeasurement {
+            address,
+            date,
+            temperature,
+            channels: Channels::default(),
+        })
+    }
+}
+
+/// What happened to the rows of an import.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// How many measurements were stored
+    pub imported: u64,
+    /// The line numbers of rows that were already stored, or that appeared earlier in the file
+    pub duplicates: Vec<u64>,
+    /// The line numbers of rows that couldn't be stored, and why
+    pub skipped: Vec<(u64, RowError)>,
+}
+
+/// Load the measurements in a CSV file into the database.
+///
+/// The file is read and stored a batch at a time, so files bigger than memory are fine. Rows that
+/// can't be read are skipped, and duplicates are left out, but neither stops the import. Rows for
+/// devices in `devices` are checked against their plausible ranges.
+pub fn import(
+    database: &Database,
+    devices: &BTreeMap<BleAddress, Device>,
+    reader: impl BufRead,
+    options: &ImportOptions,
+) -> Result<ImportReport, ImportError> {
+    let mut records = CsvRecords::new(reader);
+    let header = if options.header {
+        match records.next() {
+            Some(Ok((_, header))) => Some(header),
+            Some(Err(RecordError::Io(e))) => return Err(ImportError::Io(e)),
+            Some(Err(RecordError::UnterminatedQuote(_))) => {
+                return Err(ImportError::UnterminatedHeader)
+            }
+            None => None,
+        }
+    } else {
+        None
+    };
+    let columns = options.columns(header.as_deref())?;
+
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut lines = Vec::with_capacity(BATCH_SIZE);
+    for record in records {
+        let (line, record) = match record {
+            Ok(record) => record,
+            Err(RecordError::Io(e)) => return Err(ImportError::Io(e)),
+            Err(RecordError::UnterminatedQuote(line)) => {
+                report.skipped.push((line, RowError::UnterminatedQuote));
+                continue;
+            }
+        };
+        let measurement = columns
+            .measurement(&record)
+            .and_then(|measurement| check_plausible(devices, measurement));
+        match measurement {
+            Ok(measurement) => {
+                batch.push(measurement);
+                lines.push(line);
+            }
+            Err(e) => report.skipped.push((line, e)),
+        }
+
+        if batch.len() >= BATCH_SIZE {
+            store(database, &mut batch, &mut lines, &mut report)?;
+        }
+    }
+    store(database, &mut batch, &mut lines, &mut report)?;
+
+    Ok(report)
+}
+
+/// Make sure that a measurement is within its device's plausible range, if the device rejects
+/// readings outside of it, the same as for new measurements.
+fn check_plausible(
+    devices: &BTreeMap<BleAddress, Device>,
+    measurement: NewMeasurement,
+) -> Result<NewMeasurement, RowError> {
+    let device = match devices.get(&measurement.address) {
+        Some(device) => device,
+        None => return Ok(measurement),
+    };
+    if let Some(range) = device.plausible_range {
+        let temperature = device
+            .calibrations
+            .apply(measurement.temperature, measurement.date);
+        if range.action == OutOfRangeAction::Reject && !range.contains(temperature) {
+            return Err(RowError::Implausible(temperature, range));
+        }
+    }
+
+    Ok(measurement)
+}
+
+/// Store a batch of measurements, note what happened to each row, and empty the batch.
+fn store(
+    database: &Database,
+    batch: &mut Vec<NewMeasurement>,
+    lines: &mut Vec<u64>,
+    report: &mut ImportReport,
+) -> Result<(), ImportError> {
+    let results = database
+        .insert_measurements(batch)
+        .map_err(ImportError::Database)?;
+    for (line, result) in lines.drain(..).zip(results) {
+        match result {
+            InsertResult::Inserted => report.imported += 1,
+            InsertResult::Duplicate => report.duplicates.push(line),
+            InsertResult::Failed(reason) => report.skipped.push((line, RowError::Rejected(reason))),
+        }
+    }
+    batch.clear();
+
+    Ok(())
+}
+
+/// Parse a timestamp in any of the formats that [`ImportOptions::timestamp`] allows.
+fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Some(date.with_timezone(&Utc));
+    }
+    for format in &["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(Utc.from_utc_datetime(&date));
+        }
+    }
+    s.parse::<i64>()
+        .ok()
+        .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+}
+
+/// Why a record couldn't be read.
+#[derive(Debug)]
+pub enum RecordError {
+    /// The file couldn't be read.
+    Io(std::io::Error),
+    /// A quoted field that starts in the record on this line is never closed.
+    UnterminatedQuote(u64),
+}
+
+impl std::fmt::Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            RecordError::Io(e) => write!(f, "Could not read the file: {}", e),
+            RecordError::UnterminatedQuote(line) => write!(
+                f,
+                "The quoted field on line {} isn't closed within {} lines or by the end of the \
+                 file",
+                line, MAX_RECORD_LINES
+            ),
+        }
+    }
+}
+
+/// The records of a CSV file, with the line number that each one starts on.
+///
+/// Fields can be quoted, and quoted fields can have commas, doubled quotes, and line breaks in
+/// them. Blank lines are skipped. A quoted field that isn't closed within a hundred lines (or by
+/// the end of the file) is reported as an error, and reading carries on from the line after the
+/// one it started on, so one stray quote only costs one row.
+pub struct CsvRecords<R> {
+    reader: R,
+    line: u64,
+    /// Lines that were read as part of a record that turned out to be broken, to be read again.
+    unread: VecDeque<String>,
+}
+
+impl<R: BufRead> CsvRecords<R> {
+    /// Read records from a file (or anything else that can be read a line at a time).
+    pub fn new(reader: R) -> Self {
+        CsvRecords {
+            reader,
+            line: 0,
+            unread: VecDeque::new(),
+        }
+    }
+
+    /// Read the next line, or `None` at the end of the file.
+    fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        let line = match self.unread.pop_front() {
+            Some(line) => line,
+            None => {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                line
+            }
+        };
+        self.line += 1;
+
+        // Spreadsheets like to start their files with a byte order mark.
+        if self.line == 1 {
+            return Ok(Some(line.trim_start_matches('\u{feff}').into()));
+        }
+        Ok(Some(line))
+    }
+}
+
+impl<R: BufRead> Iterator for CsvRecords<R> {
+    type Item = Result<(u64, Vec<String>), RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut lines: Vec<String> = Vec::new();
+        let mut quotes = 0;
+        let mut start = self.line + 1;
+        loop {
+            let line = match self.read_line() {
+                Ok(Some(line)) => line,
+                Ok(None) if lines.is_empty() => return None,
+                Ok(None) => break,
+                Err(e) => return Some(Err(RecordError::Io(e))),
+            };
+            if lines.is_empty() && line.trim().is_empty() {
+                start = self.line + 1;
+                continue;
+            }
+
+            quotes += line.matches('"').count();
+            lines.push(line);
+            // An odd number of quotes means a quoted field is still going on the next line.
+            if quotes.is_multiple_of(2) || lines.len() >= MAX_RECORD_LINES {
+                break;
+            }
+        }
+
+        if !quotes.is_multiple_of(2) {
+            // Read everything after the first line again, as records of their own.
+            for line in lines.drain(1..).rev() {
+                self.line -= 1;
+                self.unread.push_front(line);
+            }
+            return Some(Err(RecordError::UnterminatedQuote(start)));
+        }
+
+        Some(Ok((start, parse_record(&lines.concat()))))
+    }
+}
+
+/// Split a single record into its fields.
+fn parse_record(text: &str) -> Vec<String> {
+    let text = text.trim_end_matches(['\r', '\n']);
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read every record of a file, as (line, fields).
+    fn records(csv: &str) -> Vec<(u64, Vec<String>)> {
+        CsvRecords::new(csv.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn plain_records() {
+        assert_eq!(
+            records("address,timestamp,temperature\r\nf4d55889b1d6,1572609600,20\r\n"),
+            vec![
+                (
+                    1,
+                    vec!["address".into(), "timestamp".into(), "temperature".into()]
+                ),
+                (
+                    2,
+                    vec!["f4d55889b1d6".into(), "1572609600".into(), "20".into()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_fields() {
+        assert_eq!(
+            records("\"a, b\",\"say \"\"hi\"\"\",\"two\nlines\",\"\"\nnext\n"),
+            vec![
+                (
+                    1,
+                    vec![
+                        "a, b".into(),
+                        "say \"hi\"".into(),
+                        "two\nlines".into(),
+                        "".into()
+                    ]
+                ),
+                (3, vec!["next".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_lines_and_byte_order_mark() {
+        assert_eq!(
+            records("\u{feff}first\n\n   \nsecond\n\n"),
+            vec![(1, vec!["first".into()]), (4, vec!["second".into()])]
+        );
+    }
+
+    #[test]
+    fn last_line_without_newline() {
+        assert_eq!(
+            records("a,b\nc,d"),
+            vec![
+                (1, vec!["a".into(), "b".into()]),
+                (2, vec!["c".into(), "d".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let csv = "a,\"stray\nb,c\nd,e\n";
+        let records: Vec<_> = CsvRecords::new(csv.as_bytes()).collect();
+        match records[0] {
+            Err(RecordError::UnterminatedQuote(1)) => {}
+            ref other => panic!("expected an unterminated quote, got {:?}", other),
+        }
+        // The lines after the stray quote are still read.
+        assert_eq!(
+            records[1].as_ref().unwrap(),
+            &(2, vec!["b".into(), "c".into()])
+        );
+        assert_eq!(
+            records[2].as_ref().unwrap(),
+            &(3, vec!["d".into(), "e".into()])
+        );
+        assert_eq!(records.len(), 3);
+    }
+
+    #[test]
+    fn long_quoted_field() {
+        let csv = format!("\"{}\nnext\n", "x\n".repeat(MAX_RECORD_LINES));
+        let records: Vec<_> = CsvRecords::new(csv.as_bytes()).collect();
+        match records[0] {
+            Err(RecordError::UnterminatedQuote(1)) => {}
+            ref other => panic!("expected an unterminated quote, got {:?}", other),
+        }
+        assert_eq!(
+            records.last().unwrap().as_ref().unwrap(),
+            &(MAX_RECORD_LINES as u64 + 2, vec!["next".into()])
+        );
+    }
+
+    #[test]
+    fn implausible_rows() {
+        let address: BleAddress = "f4d55889b1d6".parse().unwrap();
+        let mut range = PlausibleRange {
+            min: Some(Celsius::from(-10.0)),
+            max: Some(Celsius::from(45.0)),
+            action: OutOfRangeAction::Reject,
+        };
+        let mut devices = BTreeMap::new();
+        devices.insert(
+            address.clone(),
+            Device {
+                address: address.clone(),
+                name: None,
+                description: None,
+                calibrations: Default::default(),
+                plausible_range: Some(range),
+            },
+        );
+        let measurement = |temperature: f64| NewMeasurement {
+            address: address.clone(),
+            date: Utc.timestamp_opt(1_572_609_600, 0).unwrap(),
+            temperature: Celsius::from(temperature),
+            channels: Channels::default(),
+        };
+
+        assert!(check_plausible(&devices, measurement(20.0)).is_ok());
+        match check_plausible(&devices, measurement(80.0)) {
+            Err(RowError::Implausible(..)) => {}
+            other => panic!("expected an implausible row, got {:?}", other),
+        }
+
+        // Devices that only flag readings outside of their range still get them.
+        range.action = OutOfRangeAction::Flag;
+        devices.get_mut(&address).unwrap().plausible_range = Some(range);
+        assert!(check_plausible(&devices, measurement(80.0)).is_ok());
+    }
+
+    #[test]
+    fn timestamps() {
+        let expected = Utc.timestamp_opt(1_572_609_600, 0).single();
+        assert_eq!(parse_timestamp("2019-11-01T12:00:00Z"), expected);
+        assert_eq!(parse_timestamp("2019-11-01T07:00:00-05:00"), expected);
+        assert_eq!(parse_timestamp("2019-11-01 12:00:00"), expected);
+        assert_eq!(parse_timestamp("2019-11-01T12:00:00.000"), expected);
+        assert_eq!(parse_timestamp("1572609600"), expected);
+        assert_eq!(parse_timestamp("2019-11-01"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn columns_by_number() {
+        let options = ImportOptions {
+            address: "2".parse().unwrap(),
+            timestamp: "1".parse().unwrap(),
+            temperature: "3".parse().unwrap(),
+            header: false,
+            ..ImportOptions::default()
+        };
+        let columns = options.columns(None).unwrap();
+
+        let record = vec!["1572609600".into(), "f4d55889b1d6".into(), "20.5".into()];
+        let measurement = columns.measurement(&record).unwrap();
+        assert_eq!(measurement.address.to_string(), "f4d55889b1d6");
+        assert_eq!(measurement.temperature.value(), 20.5);
+
+        let short = vec!["1572609600".into(), "f4d55889b1d6".into()];
+        assert_eq!(
+            columns.measurement(&short).unwrap_err().to_string(),
+            "The row doesn't have column 3"
+        );
+    }
+
+    #[test]
+    fn missing_and_invalid_columns() {
+        let header = vec!["address".to_string(), "when".into(), "temperature".into()];
+        assert_eq!(
+            ImportOptions::default()
+                .columns(Some(&header))
+                .unwrap_err()
+                .to_string(),
+            "The file doesn't have the \"timestamp\" column"
+        );
+        assert!("0".parse::<Column>().is_err());
+        assert!(" ".parse::<Column>().is_err());
+    }
+
+    #[test]
+    fn impossible_temperatures() {
+        let columns = ImportOptions::default()
+            .columns(Some(&[
+                "address".into(),
+                "timestamp".into(),
+                "temperature".into(),
+            ]))
+            .unwrap();
+
+        let record = vec!["f4d55889b1d6".into(), "1572609600".into(), "-300".into()];
+        match columns.measurement(&record) {
+            Err(RowError::ImpossibleTemperature(_)) => {}
+            other => panic!("expected an impossible temperature, got {:?}", other),
+        }
+    }
+}
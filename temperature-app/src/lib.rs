@@ -31,6 +31,24 @@
 //! }
 //! ```
 //!
+//! Sensors that measure more than temperature can send their other readings along with it:
+//! `humidity` (relative, in percent), `pressure` (in hectopascals), and `battery` (in percent).
+//!
+//! ```graphql
+//! mutation {
+//!   addMeasurement(address: "f4d55889b1d6", tempC: 19.0, humidity: 45.0) {
+//!     date
+//!     humidity
+//!     dewPoint
+//!     heatIndex(unit: FAHRENHEIT)
+//!     absoluteHumidity
+//!   }
+//! }
+//! ```
+//!
+//! Measurements with humidity also have a dew point, a heat index, and an absolute humidity
+//! worked out from the temperature and humidity together.
+//!
 //! And there is a query called `device` which returns information about a device and can return
 //! its measurements.
 //!
@@ -54,6 +72,222 @@
 //! }
 //! ```
 //!
+//! Temperatures can also be read (and written) in whatever unit is convenient, by passing a
+//! `unit` of `CELSIUS`, `FAHRENHEIT` or `KELVIN`.
+//!
+//! ```graphql
+//! query {
+//!   device(address:"f4d55889b1d6") {
+//!     currentMeasurement {
+//!       temperature(unit: KELVIN)
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! The homepage shows a card for every device in the registry, marking devices that haven't
+//! reported for five minutes as stale, and for an hour as offline.
+//!
+//! Below the cards, it charts each device's history over the last hour, day, week, or month.
+//! The chart is drawn from the `series` field, which summarizes a device's measurements into
+//! evenly spaced points, so that a month of history doesn't mean downloading every measurement.
+//!
+//! ```graphql
+//! query {
+//!   device(address: "f4d55889b1d6") {
+//!     series(from: "2019-11-01T00:00:00Z", points: 100) {
+//!       date
+//!       min
+//!       mean
+//!       max
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! For places that can't run JavaScript, the server draws the same kind of chart itself at
+//! `/chart.svg?address=f4d55889b1d6,d0f7083ca3b1&from=2019-11-01T00:00:00Z&unit=F`. `to`
+//! defaults to now, `from` to a day before that, and `width` and `height` to 800 by 300 pixels.
+//! Built with `--features png`, the server also serves `/chart.png`.
+//!
+//! For spreadsheets, `/export?address=f4d55889b1d6,d0f7083ca3b1&from=2019-11-01T00:00:00Z`
+//! downloads every measurement the devices took, oldest first, as CSV (or as newline-delimited
+//! JSON with `format=ndjson`). Each row has the raw and calibrated temperatures in Celsius and
+//! Fahrenheit, and the device's name. The same export can be written to standard output with
+//! `graphql-server --sensors sensors.toml export f4d55889b1d6 --from 2019-11-01T00:00:00Z`.
+//!
+//! Going the other way, history from CSV files can be loaded with `graphql-server import`. The
+//! columns default to `address`, `timestamp`, and `temperature` (in Celsius), and can be renamed
+//! or given by number. Rows that can't be read are skipped, and rows that are already stored are
+//! left out, so loading the same file twice is harmless.
+//!
+//! ```bash
+//! graphql-server import old-readings.csv --temperature-column temp_f --unit fahrenheit
+//! ```
+//!
+//! The homepage shows temperatures in the server's display unit, which is set with
+//! `graphql-server --unit celsius` (Fahrenheit by default). Any request can override it with an
+//! `X-Temperature-Unit` header (one that isn't a unit is ignored), and the homepage passes its own
+//! `?unit=` along that way. Clients can find out which unit to use with the `settings` query.
+//!
+//! ```graphql
+//! query {
+//!   settings {
+//!     unit
+//!     unitSymbol
+//!   }
+//! }
+//! ```
+//!
+//! Devices are kept in a device registry in ElasticSearch, which is seeded from `sensors.toml`
+//! when the server starts, and can be changed with the `upsertDevice` and `removeDevice`
+//! mutations.
+//!
+//! ```graphql
+//! mutation {
+//!   upsertDevice(address: "f4d55889b1d6", name: "Basement", adjustment: 0.5) {
+//!     name
+//!     adjustment
+//!   }
+//! }
+//! ```
+//!
+//! Sensors that are off by different amounts at different temperatures can be given a
+//! calibration instead of an adjustment: either a gain and an offset, or a table of (raw, actual)
+//! points that readings are interpolated between. See the [`calibration`] module.
+//!
+//! ```graphql
+//! mutation {
+//!   upsertDevice(
+//!     address: "d0f7083ca3b1",
+//!     calibration: { points: [{ raw: 10.0, actual: 8.0 }, { raw: 30.0, actual: 22.0 }] }
+//!   ) {
+//!     calibration {
+//!       kind
+//!       points { raw actual }
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! Calibrations are applied as measurements are read, so each device keeps a history of them,
+//! and every measurement uses the calibration that was in effect when it was taken. Recalibrating
+//! a device with `upsertDevice` applies from `effectiveFrom` on (or from now, without it), without
+//! changing what its older measurements mean.
+//!
+//! Working out a calibration by hand is tedious, so there's a calibration assistant: put a
+//! sensor next to one that you trust for a while, and the `calibrationFit` query (or
+//! `graphql-server calibrate`) fits an offset, or a gain and an offset, to their readings. See
+//! the [`assistant`] module.
+//!
+//! ```graphql
+//! query {
+//!   calibrationFit(
+//!     address: "d0f7083ca3b1",
+//!     reference: "f4d55889b1d6",
+//!     from: "2019-11-01T00:00:00Z",
+//!     fitGain: true
+//!   ) {
+//!     pairs
+//!     rmsError
+//!     sensorsToml
+//!   }
+//! }
+//! ```
+//!
+//! Readings that can't be temperatures (not a number, or below absolute zero) are always
+//! rejected by `addMeasurement`. A device can also be given a plausible range, and calibrated
+//! readings outside of it are either rejected with an error, or stored and marked with
+//! `plausible: false` so that they can be left out of charts and alerts.
+//!
+//! ```graphql
+//! mutation {
+//!   upsertDevice(
+//!     address: "f4d55889b1d6",
+//!     plausibleRange: { min: -10.0, max: 45.0, action: FLAG }
+//!   ) {
+//!     plausibleRange { min max action }
+//!   }
+//! }
+//! ```
+//!
+//! Problems with `sensors.toml` (duplicate or malformed addresses, unbelievable adjustments or
+//! calibrations, backwards plausible ranges, misspelled keys) are reported with the line and column they're on. To check a
+//! file without starting the server, run `graphql-server check-config sensors.toml`, and to
+//! refuse to start with a broken file, pass `--strict-config`.
+//!
+//! For consumers that don't want to speak GraphQL, every measurement accepted by `addMeasurement`
+//! is also streamed as Server-Sent Events from `/events` (optionally filtered with
+//! `?address=f4d55889b1d6`). Clients that reconnect with a `Last-Event-ID` header get the
+//! measurements they missed replayed from ElasticSearch.
+//!
+//! ```bash
+//! curl -N http://localhost:8080/events
+//! ```
+//!
+//! For Prometheus, `/metrics` reports how many GraphQL requests the server has answered and how
+//! long they took, how long requests to ElasticSearch took and how many failed, and the latest
+//! calibrated temperature of every device in the registry, labeled with its address and name.
+//!
+//! ```yaml
+//! scrape_configs:
+//!   - job_name: temperature-app
+//!     static_configs:
+//!       - targets: ["localhost:8080"]
+//! ```
+//!
+//! Sensors that Prometheus already scrapes can be stored without going through GraphQL. Start the
+//! server with `--remote-write-metric room_temperature_celsius` (and, if the metric doesn't carry
+//! the device's BLE address in an `address` label or isn't in Celsius,
+//! `--remote-write-address-label` and `--remote-write-unit`), and every sample of that metric sent
+//! to `/api/v1/write` is checked the same way `addMeasurement` would check it, and stored. Samples
+//! that are already stored are left alone, so Prometheus retrying a request is harmless.
+//!
+//! ```yaml
+//! remote_write:
+//!   - url: http://localhost:8080/api/v1/write
+//!     write_relabel_configs:
+//!       - source_labels: [__name__]
+//!         regex: room_temperature_celsius
+//!         action: keep
+//! ```
+//!
+//! Gateways that speak InfluxDB line protocol, like Telegraf and ESPHome bridges, can write to
+//! `/write` instead once the server is started with `--influx-write`, with the device's address in
+//! an `address` tag and the temperature in a `temp_c` or `temp_f` field. See the
+//! [`line_protocol`] module.
+//!
+//! ```bash
+//! graphql-server --influx-write
+//! curl -i "http://localhost:8080/write?precision=s" \
+//!   --data-binary "temperature,address=f4d55889b1d6 temp_c=21.5,humidity=45 1572609600"
+//! ```
+//!
+//! Sensors that publish to MQTT can be picked up by giving the server a broker to subscribe to.
+//! Each `--mqtt-topic` has `{address}` where the device's address is (and can use MQTT's `+` and
+//! `#` wildcards), and each message is either a bare temperature (in `--mqtt-unit`, Celsius by
+//! default) or a JSON object. The server reconnects by itself if the broker goes away. See the
+//! [`mqtt`] module.
+//!
+//! ```bash
+//! graphql-server --mqtt-broker mqtt://localhost:1883 --mqtt-topic "esp32/{address}/temperature"
+//! mosquitto_pub -t esp32/f4d55889b1d6/temperature -m '{"temp_c": 21.5, "battery": 80}'
+//! ```
+//!
+//! Docker Compose runs a Mosquitto broker for the server to subscribe to, on the default
+//! `sensors/{address}/temperature` topics. With everything up, `./mqtt-test.sh` publishes a
+//! measurement to it and checks that the server stored it.
+//!
+//! Every accepted measurement can also be written on to InfluxDB or Graphite, for anybody who'd
+//! rather chart it in Grafana. Each sink has its own queue, so one that's slow or down never holds
+//! up measurements coming in; it's retried until it's back, and measurements are dropped for it if
+//! it falls too far behind. See the [`sinks`] module.
+//!
+//! ```bash
+//! graphql-server --influx-sink "http://localhost:8086/write?db=temperatures" \
+//!   --graphite-sink localhost:2003 --graphite-prefix home.temperature
+//! ```
+//!
 //! *Most* of the logic is contained inside this library so that `cargo doc` can be used to
 //! generate documentation. Two binaries also exist:
 //!
@@ -74,6 +308,18 @@
 //! away.) ElasticSearch is exposed on port 9200.
 
 #![deny(missing_docs)]
+pub mod address;
+pub mod assistant;
+pub mod calibration;
+pub mod channels;
+pub mod chart;
 pub mod database;
+pub mod events;
+pub mod export;
 pub mod graphql;
+pub mod import;
+pub mod line_protocol;
+pub mod metrics;
+pub mod mqtt;
+pub mod sinks;
 pub mod temperature;
@@ -0,0 +1,500 @@
+//! Reading measurements written in InfluxDB line protocol
+//!
+//! Off-the-shelf gateways like Telegraf, and bridges for ESPHome, know how to send readings to
+//! InfluxDB. Each line is a measurement name, some tags, some fields, and an optional timestamp:
+//!
+//! ```text
+//! temperature,address=f4d55889b1d6 temp_c=21.5,humidity=45 1572609600000000000
+//! ```
+//!
+//! The measurement name doesn't matter. The `address` tag says which device took the reading,
+//! and the temperature is in either a `temp_c` or a `temp_f` field. `humidity`, `pressure`, and
+//! `battery` fields are stored too, and any other fields are ignored. Timestamps are in
+//! nanoseconds unless a different [`Precision`] is given, and lines without one were taken when
+//! they were received.
+//!
+//! ```
+//! # use chrono::Utc;
+//! # use temperature_app::line_protocol::{Point, Precision};
+//! let point: Point = "temperature,address=f4d55889b1d6 temp_f=68,battery=90i 1572609600"
+//!     .parse()
+//!     .unwrap();
+//! let measurement = point.new_measurement(Precision::Seconds, Utc::now()).unwrap();
+//! assert_eq!(measurement.address.to_string(), "f4d55889b1d6");
+//! assert_eq!(measurement.temperature.value(), 20.0);
+//! assert_eq!(measurement.channels.battery.unwrap().value(), 90.0);
+//! assert_eq!(measurement.date.to_rfc3339(), "2019-11-01T12:00:00+00:00");
+//!
+//! let error = "temperature,address=f4d55889b1d6 temp_c=warm"
+//!     .parse::<Point>()
+//!     .unwrap_err();
+//! assert_eq!(error.to_string(), "invalid field value: warm");
+//! ```
+
+use crate::address::BleAddress;
+use crate::channels::Channels;
+use crate::database::NewMeasurement;
+use crate::temperature::{Celsius, Fahrenheit};
+use chrono::{DateTime, TimeZone, Utc};
+
+/// What unit a line's timestamp is in, as given by the `precision` parameter. InfluxDB assumes
+/// nanoseconds when it isn't given.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Precision {
+    /// Nanoseconds, `n` or `ns`
+    #[default]
+    Nanoseconds,
+    /// Microseconds, `u`, `us`, or `µ`
+    Microseconds,
+    /// Milliseconds, `ms`
+    Milliseconds,
+    /// Seconds, `s`
+    Seconds,
+    /// Minutes, `m`
+    Minutes,
+    /// Hours, `h`
+    Hours,
+}
+
+impl std::str::FromStr for Precision {
+    type Err = LineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "n" | "ns" => Ok(Precision::Nanoseconds),
+            "u" | "us" | "µ" | "µs" => Ok(Precision::Microseconds),
+            "ms" => Ok(Precision::Milliseconds),
+            "s" => Ok(Precision::Seconds),
+            "m" => Ok(Precision::Minutes),
+            "h" => Ok(Precision::Hours),
+            _ => Err(LineError::UnknownPrecision(s.into())),
+        }
+    }
+}
+
+impl Precision {
+    /// Turn a timestamp in this precision into a date, if it's one that chrono can represent.
+    pub fn date(self, timestamp: i64) -> Option<DateTime<Utc>> {
+        // How many of this unit there are in a second, and how many seconds there are in one.
+        let (per_second, seconds_each) = match self {
+            Precision::Nanoseconds => (1_000_000_000, 1),
+            Precision::Microseconds => (1_000_000, 1),
+            Precision::Milliseconds => (1_000, 1),
+            Precision::Seconds => (1, 1),
+            Precision::Minutes => (1, 60),
+            Precision::Hours => (1, 60 * 60),
+        };
+        let seconds = timestamp.div_euclid(per_second).checked_mul(seconds_each)?;
+        let nanoseconds = timestamp.rem_euclid(per_second) * (1_000_000_000 / per_second);
+        Utc.timestamp_opt(seconds, nanoseconds as u32).single()
+    }
+}
+
+/// Why a line couldn't be read, or couldn't be stored as a measurement. These are worded like
+/// InfluxDB's own errors, so that they look familiar in a gateway's logs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineError {
+    /// The `precision` parameter isn't one that InfluxDB accepts.
+    UnknownPrecision(String),
+    /// The line doesn't start with a measurement name.
+    MissingMeasurement,
+    /// A tag doesn't have an `=` and a value.
+    MissingTagValue(String),
+    /// The line doesn't have any fields.
+    MissingFields,
+    /// A field doesn't have an `=` and a value.
+    MissingFieldValue(String),
+    /// A field's value isn't a number, a string, or a boolean.
+    InvalidFieldValue(String),
+    /// The timestamp isn't a whole number, or is too far from now to be a date.
+    InvalidTimestamp(String),
+    /// There's something after the timestamp.
+    TrailingData(String),
+    /// The line doesn't have an `address` tag.
+    MissingAddress,
+    /// The `address` tag isn't a BLE address.
+    InvalidAddress(String),
+    /// The line doesn't have a `temp_c` or `temp_f` field.
+    MissingTemperature,
+    /// One of the fields that's stored isn't a number.
+    NotANumber(String),
+}
+
+impl std::fmt::Display for LineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            LineError::UnknownPrecision(precision) => {
+                write!(f, "invalid precision \"{}\"", precision)
+            }
+            LineError::MissingMeasurement => write!(f, "missing measurement"),
+            LineError::MissingTagValue(tag) => write!(f, "missing tag value: {}", tag),
+            LineError::MissingFields => write!(f, "missing fields"),
+            LineError::MissingFieldValue(field) => write!(f, "missing field value: {}", field),
+            LineError::InvalidFieldValue(value) => write!(f, "invalid field value: {}", value),
+            LineError::InvalidTimestamp(timestamp) => {
+                write!(f, "bad timestamp: {}", timestamp)
+            }
+            LineError::TrailingData(data) => write!(f, "unexpected data after timestamp: {}", data),
+            LineError::MissingAddress => write!(f, "missing address tag"),
+            LineError::InvalidAddress(address) => {
+                write!(f, "\"{}\" is not a BLE address", address)
+            }
+            LineError::MissingTemperature => write!(f, "missing temp_c or temp_f field"),
+            LineError::NotANumber(field) => write!(f, "field {} is not a number", field),
+        }
+    }
+}
+
+/// The value of a field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    /// A number like `21.5`
+    Float(f64),
+    /// A signed integer like `21i`
+    Integer(i64),
+    /// An unsigned integer like `21u`
+    UInteger(u64),
+    /// A quoted string like `"upstairs"`
+    String(String),
+    /// `true` or `false`, or any of their abbreviations
+    Boolean(bool),
+}
+
+impl FieldValue {
+    /// The value as a number, if it is one.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            FieldValue::Float(value) => Some(value),
+            FieldValue::Integer(value) => Some(value as f64),
+            FieldValue::UInteger(value) => Some(value as f64),
+            FieldValue::String(_) | FieldValue::Boolean(_) => None,
+        }
+    }
+}
+
+impl std::str::FromStr for FieldValue {
+    type Err = LineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || LineError::InvalidFieldValue(s.into());
+
+        if let Some(quoted) = s.strip_prefix('"') {
+            let string = quoted.strip_suffix('"').ok_or_else(invalid)?;
+            return Ok(FieldValue::String(unescape(string)));
+        }
+
+        match s {
+            "t" | "T" | "true" | "True" | "TRUE" => return Ok(FieldValue::Boolean(true)),
+            "f" | "F" | "false" | "False" | "FALSE" => return Ok(FieldValue::Boolean(false)),
+            _ => {}
+        }
+
+        if let Some(integer) = s.strip_suffix('i') {
+            integer
+                .parse()
+                .map(FieldValue::Integer)
+                .map_err(|_| invalid())
+        } else if let Some(integer) = s.strip_suffix('u') {
+            integer
+                .parse()
+                .map(FieldValue::UInteger)
+                .map_err(|_| invalid())
+        } else {
+            // Rust happily parses "NaN" and "inf", but InfluxDB doesn't.
+            match s.parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(FieldValue::Float(value)),
+                _ => Err(invalid()),
+            }
+        }
+    }
+}
+
+/// One line of line protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    /// The measurement name, like `temperature`
+    pub measurement: String,
+    /// The tags, in the order they were written
+    pub tags: Vec<(String, String)>,
+    /// The fields, in the order they were written
+    pub fields: Vec<(String, FieldValue)>,
+    /// When the reading was taken, in whatever precision the request said
+    pub timestamp: Option<i64>,
+}
+
+impl std::str::FromStr for Point {
+    type Err = LineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sections = split(s, ' ', true).into_iter();
+        let key = sections.next().unwrap_or("");
+        let fields = sections.next().ok_or(LineError::MissingFields)?;
+        let timestamp = sections
+            .next()
+            .map(|timestamp| {
+                timestamp
+                    .parse()
+                    .map_err(|_| LineError::InvalidTimestamp(timestamp.into()))
+            })
+            .transpose()?;
+        if let Some(rest) = sections.next() {
+            return Err(LineError::TrailingData(rest.into()));
+        }
+
+        let mut key = split(key, ',', false).into_iter();
+        let measurement = unescape(key.next().unwrap_or(""));
+        if measurement.is_empty() {
+            return Err(LineError::MissingMeasurement);
+        }
+        let tags = key
+            .map(|tag| match split_once(tag) {
+                Some((name, value)) if !name.is_empty() && !value.is_empty() => {
+                    Ok((unescape(name), unescape(value)))
+                }
+                _ => Err(LineError::MissingTagValue(tag.into())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        if fields.is_empty() {
+            return Err(LineError::MissingFields);
+        }
+        let fields = split(fields, ',', true)
+            .into_iter()
+            .map(|field| match split_once(field) {
+                Some((name, value)) if !name.is_empty() && !value.is_empty() => {
+                    Ok((unescape(name), value.parse()?))
+                }
+                _ => Err(LineError::MissingFieldValue(field.into())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Point {
+            measurement,
+            tags,
+            fields,
+            timestamp,
+        })
+    }
+}
+
+impl Point {
+    /// The value of a tag, if the line has it.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of a field, if the line has it.
+    pub fn field(&self, name: &str) -> Option<&FieldValue> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Turn this line into a measurement that can be stored. Lines without a timestamp were taken
+    /// `now`. The readings aren't checked here; that happens when they're stored, the same as for
+    /// any other measurement.
+    pub fn new_measurement(
+        &self,
+        precision: Precision,
+        now: DateTime<Utc>,
+    ) -> Result<NewMeasurement, LineError> {
+        let address = self.tag("address").ok_or(LineError::MissingAddress)?;
+        let address = address
+            .parse::<BleAddress>()
+            .map_err(|_| LineError::InvalidAddress(address.into()))?;
+
+        let number = |name: &str| {
+            self.field(name)
+                .map(|value| {
+                    value
+                        .as_f64()
+                        .ok_or_else(|| LineError::NotANumber(name.into()))
+                })
+                .transpose()
+        };
+        let temperature = match (number("temp_c")?, number("temp_f")?) {
+            (Some(celsius), _) => Celsius::from(celsius),
+            (None, Some(fahrenheit)) => Fahrenheit::from(fahrenheit).into(),
+            (None, None) => return Err(LineError::MissingTemperature),
+        };
+        let channels = Channels {
+            humidity: number("humidity")?.map(Into::into),
+            pressure: number("pressure")?.map(Into::into),
+            battery: number("battery")?.map(Into::into),
+        };
+
+        let date = match self.timestamp {
+            Some(timestamp) => precision
+                .date(timestamp)
+                .ok_or_else(|| LineError::InvalidTimestamp(timestamp.to_string()))?,
+            None => now,
+        };
+
+        Ok(NewMeasurement {
+            address,
+            date,
+            temperature,
+            channels,
+        })
+    }
+}
+
+/// Parse every line of a request, skipping blank lines and comments, and keeping each line along
+/// with what became of it so that errors can quote it.
+///
+/// ```
+/// # use temperature_app::line_protocol::{parse, FieldValue};
+/// let body = "# From the garage\n\
+///             weather\\ station,address=aabbccddeeff,room=Back\\ Porch temp_c=4,note=\"a, b\"\n";
+/// let points: Vec<_> = parse(body).collect();
+/// assert_eq!(points.len(), 1);
+///
+/// let point = points[0].1.as_ref().unwrap();
+/// assert_eq!(point.measurement, "weather station");
+/// assert_eq!(point.tag("room"), Some("Back Porch"));
+/// assert_eq!(point.field("note"), Some(&FieldValue::String("a, b".into())));
+/// assert_eq!(point.timestamp, None);
+/// ```
+pub fn parse(body: &str) -> impl Iterator<Item = (&str, Result<Point, LineError>)> {
+    body.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| (line, line.parse()))
+}
+
+/// Split on a separator, except where it's escaped with a backslash, or (if `quotes` is set)
+/// inside of a quoted string field.
+fn split(s: &str, separator: char, quotes: bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut quoted = false;
+    for (index, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if quotes && c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            parts.push(&s[start..index]);
+            start = index + c.len_utf8();
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Split a tag or field at its first unescaped `=`. Only the first one separates the name from
+/// the value, because a string value can have its own.
+fn split_once(s: &str) -> Option<(&str, &str)> {
+    let name = split(s, '=', false)[0];
+    if name.len() == s.len() {
+        None
+    } else {
+        Some((name, &s[name.len() + 1..]))
+    }
+}
+
+/// Remove the backslashes from escaped characters.
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next)) if [',', ' ', '=', '"', '\\'].contains(&next) => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precisions() {
+        let date =
+            |precision: Precision, timestamp| precision.date(timestamp).unwrap().to_rfc3339();
+        assert_eq!(
+            date(Precision::Nanoseconds, 1_572_609_600_123_456_789),
+            "2019-11-01T12:00:00.123456789+00:00"
+        );
+        assert_eq!(
+            date(Precision::Microseconds, 1_572_609_600_123_456),
+            "2019-11-01T12:00:00.123456+00:00"
+        );
+        assert_eq!(
+            date(Precision::Milliseconds, 1_572_609_600_123),
+            "2019-11-01T12:00:00.123+00:00"
+        );
+        assert_eq!(
+            date(Precision::Seconds, 1_572_609_600),
+            "2019-11-01T12:00:00+00:00"
+        );
+        assert_eq!(
+            date(Precision::Minutes, 26_210_160),
+            "2019-11-01T12:00:00+00:00"
+        );
+        assert_eq!(date(Precision::Hours, 436_836), "2019-11-01T12:00:00+00:00");
+    }
+
+    #[test]
+    fn timestamps_before_1970() {
+        assert_eq!(
+            Precision::Milliseconds.date(-1).unwrap().to_rfc3339(),
+            "1969-12-31T23:59:59.999+00:00"
+        );
+        assert_eq!(
+            Precision::Nanoseconds
+                .date(-1_500_000_000)
+                .unwrap()
+                .to_rfc3339(),
+            "1969-12-31T23:59:58.500+00:00"
+        );
+    }
+
+    #[test]
+    fn timestamps_too_far_away() {
+        assert_eq!(Precision::Hours.date(i64::MAX), None);
+        assert_eq!(Precision::Seconds.date(i64::MIN), None);
+
+        let point: Point = "temperature,address=f4d55889b1d6 temp_c=20 9223372036854775807"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            point.new_measurement(Precision::Minutes, Utc::now()).err(),
+            Some(LineError::InvalidTimestamp("9223372036854775807".into()))
+        );
+
+        assert_eq!(
+            "temperature,address=f4d55889b1d6 temp_c=20 9223372036854775808".parse::<Point>(),
+            Err(LineError::InvalidTimestamp("9223372036854775808".into()))
+        );
+    }
+
+    #[test]
+    fn escaped_tags() {
+        let point: Point = r"temperature,address=f4d55889b1d6,room\,name=Back\ Porch\=1 temp_c=20"
+            .parse()
+            .unwrap();
+        assert_eq!(point.tag("room,name"), Some("Back Porch=1"));
+        assert_eq!(point.tag("address"), Some("f4d55889b1d6"));
+    }
+
+    #[test]
+    fn trailing_data() {
+        assert_eq!(
+            "temperature,address=f4d55889b1d6 temp_c=20 1572609600 extra".parse::<Point>(),
+            Err(LineError::TrailingData("extra".into()))
+        );
+    }
+}
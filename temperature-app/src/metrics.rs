@@ -0,0 +1,202 @@
+//! Counting things for Prometheus
+//!
+//! Just enough of the Prometheus text format to describe how the server is doing: [`Counter`]s
+//! and [`Histogram`]s that can be shared between threads, and [`write_gauge`] for values that are
+//! read fresh each time. Each of them can have labels, and writes itself out in the format that
+//! the `/metrics` route serves.
+//!
+//! ```
+//! # use temperature_app::metrics::{Counter, Histogram};
+//! let failures = Counter::new("example_failures_total", "How many requests failed");
+//! failures.inc(&[("operation", "search")]);
+//! failures.inc(&[("operation", "search")]);
+//!
+//! let durations = Histogram::new("example_duration_seconds", "How long requests took");
+//! durations.observe(&[], 0.02);
+//!
+//! let mut out = String::new();
+//! failures.write(&mut out);
+//! durations.write(&mut out);
+//! assert!(out.contains("# TYPE example_failures_total counter\n"));
+//! assert!(out.contains("example_failures_total{operation=\"search\"} 2\n"));
+//! assert!(out.contains("example_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+//! assert!(out.contains("example_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+//! assert!(out.contains("example_duration_seconds_count 1\n"));
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The upper bounds of histogram buckets, in seconds. These are the Prometheus client libraries'
+/// defaults, which suit anything that takes between a few milliseconds and a few seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Label names and values, like `[("operation", "search")]`.
+pub type Labels<'a> = &'a [(&'a str, &'a str)];
+
+/// Labels as they're kept between observations
+type OwnedLabels = Vec<(String, String)>;
+
+/// A number that only goes up, like how many requests have failed.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    values: Mutex<BTreeMap<OwnedLabels, u64>>,
+}
+
+impl Counter {
+    /// Create a counter that hasn't counted anything yet.
+    pub fn new(name: &'static str, help: &'static str) -> Self {
+        Counter {
+            name,
+            help,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Add one to the count with these labels.
+    pub fn inc(&self, labels: Labels) {
+        *self.values.lock().unwrap().entry(own(labels)).or_insert(0) += 1;
+    }
+
+    /// Write the counter out in the Prometheus text format.
+    pub fn write(&self, out: &mut String) {
+        header(out, self.name, self.help, "counter");
+        for (labels, value) in self.values.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "{}{} {}",
+                self.name,
+                format_labels(labels, None),
+                value
+            );
+        }
+    }
+}
+
+/// How a histogram's observations with the same labels are spread out.
+#[derive(Default)]
+struct Buckets {
+    /// How many observations were at most each of the bounds in [`BUCKETS`]
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// How long things take, like how long GraphQL requests took to answer.
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    values: Mutex<BTreeMap<OwnedLabels, Buckets>>,
+}
+
+impl Histogram {
+    /// Create a histogram that hasn't seen anything yet.
+    pub fn new(name: &'static str, help: &'static str) -> Self {
+        Histogram {
+            name,
+            help,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record a number of seconds under these labels.
+    pub fn observe(&self, labels: Labels, seconds: f64) {
+        let mut values = self.values.lock().unwrap();
+        let buckets = values.entry(own(labels)).or_default();
+        for (count, bound) in buckets.counts.iter_mut().zip(BUCKETS.iter()) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        buckets.count += 1;
+        buckets.sum += seconds;
+    }
+
+    /// Record how long something took under these labels.
+    pub fn observe_duration(&self, labels: Labels, duration: Duration) {
+        self.observe(labels, duration.as_secs_f64());
+    }
+
+    /// Write the histogram out in the Prometheus text format.
+    pub fn write(&self, out: &mut String) {
+        header(out, self.name, self.help, "histogram");
+        for (labels, buckets) in self.values.lock().unwrap().iter() {
+            for (count, bound) in buckets.counts.iter().zip(BUCKETS.iter()) {
+                let le = bound.to_string();
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    self.name,
+                    format_labels(labels, Some(&le)),
+                    count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                self.name,
+                format_labels(labels, Some("+Inf")),
+                buckets.count
+            );
+            let labels = format_labels(labels, None);
+            let _ = writeln!(out, "{}_sum{} {}", self.name, labels, buckets.sum);
+            let _ = writeln!(out, "{}_count{} {}", self.name, labels, buckets.count);
+        }
+    }
+}
+
+/// Write out a gauge, which is a value that can go up and down, like a temperature. Gauges are
+/// worked out fresh for every scrape, so they're written straight from their values.
+pub fn write_gauge(out: &mut String, name: &str, help: &str, values: &[(Labels, f64)]) {
+    header(out, name, help, "gauge");
+    for (labels, value) in values {
+        let _ = writeln!(
+            out,
+            "{}{} {}",
+            name,
+            format_labels(&own(labels), None),
+            value
+        );
+    }
+}
+
+/// Write the lines that describe a metric.
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Copy labels so that they can be kept.
+fn own(labels: Labels) -> OwnedLabels {
+    labels
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Write labels like `{operation="search"}`, with a histogram bucket's `le` label first if there
+/// is one. Metrics without labels don't get braces at all.
+fn format_labels(labels: &[(String, String)], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = Vec::new();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    for (name, value) in labels {
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        pairs.push(format!("{}=\"{}\"", name, value));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
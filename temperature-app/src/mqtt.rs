@@ -0,0 +1,313 @@
+//! Reading measurements from MQTT messages
+//!
+//! Sensors that publish to an MQTT broker say which device they are in the topic, the payload, or
+//! both. A [`TopicPattern`] like `sensors/{address}/temperature` is subscribed to as
+//! `sensors/+/temperature`, and the level in the `{address}` position of each message's topic is
+//! the address of the device. Patterns can use MQTT's own `+` and `#` wildcards too.
+//!
+//! The payload is either a bare temperature, like `21.5` (in whatever unit the bridge is set up
+//! with, unless it says otherwise, like `70.7 °F`), or a JSON object. JSON payloads have the
+//! temperature in a `temp_c`, `temp_f`, or `temperature` field, and can also have `humidity`,
+//! `pressure`, `battery`, a `timestamp` (RFC 3339, or seconds since the UNIX epoch), and an
+//! `address` for topics that don't have one.
+//!
+//! ```
+//! # use chrono::Utc;
+//! # use temperature_app::mqtt::TopicPattern;
+//! # use temperature_app::temperature::TemperatureUnit;
+//! let pattern: TopicPattern = "sensors/{address}/temperature".parse().unwrap();
+//! assert_eq!(pattern.filter(), "sensors/+/temperature");
+//!
+//! let measurement = pattern
+//!     .measurement("sensors/f4d55889b1d6/temperature", b"68", TemperatureUnit::Fahrenheit, Utc::now())
+//!     .unwrap()
+//!     .unwrap();
+//! assert_eq!(measurement.address.to_string(), "f4d55889b1d6");
+//! assert_eq!(measurement.temperature.value(), 20.0);
+//!
+//! let measurement = pattern
+//!     .measurement(
+//!         "sensors/f4d55889b1d6/temperature",
+//!         br#"{"temp_c": 21.5, "humidity": 45, "timestamp": "2019-11-01T12:00:00Z"}"#,
+//!         TemperatureUnit::Fahrenheit,
+//!         Utc::now(),
+//!     )
+//!     .unwrap()
+//!     .unwrap();
+//! assert_eq!(measurement.temperature.value(), 21.5);
+//! assert_eq!(measurement.channels.humidity.unwrap().value(), 45.0);
+//! assert_eq!(measurement.date.to_rfc3339(), "2019-11-01T12:00:00+00:00");
+//!
+//! // Messages on topics that don't match aren't measurements at all.
+//! assert!(pattern
+//!     .measurement("sensors/f4d55889b1d6/status", b"online", TemperatureUnit::Celsius, Utc::now())
+//!     .is_none());
+//! ```
+
+use crate::address::BleAddress;
+use crate::channels::Channels;
+use crate::database::NewMeasurement;
+use crate::temperature::{Celsius, Fahrenheit, TemperatureParseError, TemperatureUnit};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+/// Errors for topic patterns that can't be subscribed to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopicError {
+    /// The pattern is empty.
+    Empty,
+    /// The pattern has `{address}` in it more than once.
+    MultipleAddresses,
+    /// A `#` isn't the last level of the pattern, or a wildcard shares a level with something else.
+    MisplacedWildcard(String),
+}
+
+impl std::fmt::Display for TopicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            TopicError::Empty => write!(f, "A topic pattern can't be empty"),
+            TopicError::MultipleAddresses => {
+                write!(f, "A topic pattern can only have one {{address}}")
+            }
+            TopicError::MisplacedWildcard(pattern) => write!(
+                f,
+                "\"{}\" is not a valid topic pattern. Wildcards and {{address}} must be a whole \
+                 level, and # can only be the last level",
+                pattern
+            ),
+        }
+    }
+}
+
+/// Why a message couldn't be turned into a measurement.
+#[derive(Debug)]
+pub enum MessageError {
+    /// The address in the topic or payload isn't a BLE address.
+    InvalidAddress(String),
+    /// Neither the topic nor the payload say which device the measurement is from.
+    MissingAddress,
+    /// The payload isn't a temperature or a JSON object.
+    InvalidPayload(TemperatureParseError),
+    /// The payload is a JSON object, but not one with the fields we expect.
+    InvalidJson(serde_json::Error),
+    /// The JSON object doesn't have a temperature.
+    MissingTemperature,
+    /// The JSON object's timestamp isn't a date.
+    InvalidTimestamp(String),
+}
+
+impl std::fmt::Display for MessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            MessageError::InvalidAddress(address) => {
+                write!(f, "\"{}\" is not a BLE address", address)
+            }
+            MessageError::MissingAddress => {
+                write!(f, "Neither the topic nor the payload have an address")
+            }
+            MessageError::InvalidPayload(e) => e.fmt(f),
+            MessageError::InvalidJson(e) => write!(f, "The payload is not valid JSON: {}", e),
+            MessageError::MissingTemperature => write!(
+                f,
+                "The payload doesn't have a temp_c, temp_f, or temperature field"
+            ),
+            MessageError::InvalidTimestamp(timestamp) => {
+                write!(f, "{} is not a timestamp", timestamp)
+            }
+        }
+    }
+}
+
+/// One level of a topic pattern
+#[derive(Debug, Clone, PartialEq)]
+enum Level {
+    /// A level that has to be exactly this
+    Exact(String),
+    /// A level that's the device's address
+    Address,
+    /// `+`, which is any one level
+    Any,
+    /// `#`, which is any number of levels, including none
+    Rest,
+}
+
+/// A topic to subscribe to, and where in it to find the device's address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicPattern {
+    levels: Vec<Level>,
+}
+
+impl std::str::FromStr for TopicPattern {
+    type Err = TopicError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(TopicError::Empty);
+        }
+
+        let parts: Vec<&str> = s.split('/').collect();
+        let mut levels = Vec::with_capacity(parts.len());
+        for (index, part) in parts.iter().enumerate() {
+            let level = match *part {
+                "{address}" => Level::Address,
+                "+" => Level::Any,
+                "#" if index == parts.len() - 1 => Level::Rest,
+                _ if part.contains(['+', '#', '{', '}']) => {
+                    return Err(TopicError::MisplacedWildcard(s.into()))
+                }
+                _ => Level::Exact(part.to_string()),
+            };
+            if level == Level::Address && levels.contains(&Level::Address) {
+                return Err(TopicError::MultipleAddresses);
+            }
+            levels.push(level);
+        }
+
+        Ok(TopicPattern { levels })
+    }
+}
+
+impl std::fmt::Display for TopicPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        let levels: Vec<&str> = self
+            .levels
+            .iter()
+            .map(|level| match level {
+                Level::Exact(level) => level.as_str(),
+                Level::Address => "{address}",
+                Level::Any => "+",
+                Level::Rest => "#",
+            })
+            .collect();
+        f.write_str(&levels.join("/"))
+    }
+}
+
+impl TopicPattern {
+    /// The MQTT topic filter to subscribe to.
+    pub fn filter(&self) -> String {
+        self.to_string().replace("{address}", "+")
+    }
+
+    /// If a topic matches this pattern, the part of it in the `{address}` position (if the
+    /// pattern has one).
+    fn matches<'a>(&self, topic: &'a str) -> Option<Option<&'a str>> {
+        let mut address = None;
+        let mut parts = topic.split('/');
+        for level in &self.levels {
+            match level {
+                Level::Rest => return Some(address),
+                Level::Any => {
+                    parts.next()?;
+                }
+                Level::Address => address = Some(parts.next()?),
+                Level::Exact(level) => {
+                    if parts.next()? != level {
+                        return None;
+                    }
+                }
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(address),
+        }
+    }
+
+    /// Turn a message into a measurement that can be stored, or `None` if its topic doesn't match
+    /// this pattern. Bare temperatures without a unit are taken to be in `unit`, and messages
+    /// without a timestamp were taken `now`. The readings aren't checked here; that happens when
+    /// they're stored, the same as for any other measurement.
+    pub fn measurement(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        unit: TemperatureUnit,
+        now: DateTime<Utc>,
+    ) -> Option<Result<NewMeasurement, MessageError>> {
+        let address = self.matches(topic)?;
+        Some(parse_message(address, payload, unit, now))
+    }
+}
+
+/// A JSON payload
+#[derive(Debug, Deserialize)]
+struct JsonPayload {
+    address: Option<String>,
+    temp_c: Option<f64>,
+    temp_f: Option<f64>,
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+    pressure: Option<f64>,
+    battery: Option<f64>,
+    timestamp: Option<JsonTimestamp>,
+}
+
+/// A timestamp in a JSON payload, either as a date or as a number of seconds.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonTimestamp {
+    Seconds(i64),
+    Date(String),
+}
+
+/// Turn a payload into a measurement, given the address from its topic, if it had one.
+fn parse_message(
+    address: Option<&str>,
+    payload: &[u8],
+    unit: TemperatureUnit,
+    now: DateTime<Utc>,
+) -> Result<NewMeasurement, MessageError> {
+    let text = String::from_utf8_lossy(payload);
+    let text = text.trim();
+
+    let (address, temperature, channels, date) = if text.starts_with('{') {
+        let json: JsonPayload = serde_json::from_str(text).map_err(MessageError::InvalidJson)?;
+        let temperature = match (json.temp_c, json.temp_f, json.temperature) {
+            (Some(celsius), _, _) => Celsius::from(celsius),
+            (None, Some(fahrenheit), _) => Fahrenheit::from(fahrenheit).into(),
+            (None, None, Some(temperature)) => unit.to_celsius(temperature),
+            (None, None, None) => return Err(MessageError::MissingTemperature),
+        };
+        let channels = Channels {
+            humidity: json.humidity.map(Into::into),
+            pressure: json.pressure.map(Into::into),
+            battery: json.battery.map(Into::into),
+        };
+        let date = match json.timestamp {
+            None => now,
+            Some(JsonTimestamp::Seconds(seconds)) => Utc
+                .timestamp_opt(seconds, 0)
+                .single()
+                .ok_or_else(|| MessageError::InvalidTimestamp(seconds.to_string()))?,
+            Some(JsonTimestamp::Date(date)) => DateTime::parse_from_rfc3339(&date)
+                .map(|date| date.with_timezone(&Utc))
+                .map_err(|_| MessageError::InvalidTimestamp(date))?,
+        };
+        // The topic is where the broker's access control applies, so it wins over the payload.
+        let address = address.map(String::from).or(json.address);
+        (address, temperature, channels, date)
+    } else {
+        let temperature = unit
+            .parse_celsius(text)
+            .map_err(MessageError::InvalidPayload)?;
+        (
+            address.map(String::from),
+            temperature,
+            Channels::default(),
+            now,
+        )
+    };
+
+    let address = address.ok_or(MessageError::MissingAddress)?;
+    let address = address
+        .parse::<BleAddress>()
+        .map_err(|_| MessageError::InvalidAddress(address))?;
+
+    Ok(NewMeasurement {
+        address,
+        date,
+        temperature,
+        channels,
+    })
+}
@@ -0,0 +1,362 @@
+//! Forwarding measurements to other time series databases
+//!
+//! Every measurement that's accepted can also be written on to InfluxDB (as line protocol over
+//! HTTP) or Graphite (as plaintext over TCP), for anybody who'd rather look at it there. Each
+//! sink runs on its own thread with its own queue of measurements from the [`Broadcaster`], which
+//! drops measurements for a sink that falls too far behind rather than holding up the
+//! measurements coming in. While a sink is failing, the batch it's on is retried, waiting longer
+//! between each try.
+//!
+//! ```
+//! # use temperature_app::events::MeasurementEvent;
+//! # use temperature_app::sinks::{graphite_lines, influx_line};
+//! let event = MeasurementEvent {
+//!     address: "f4d55889b1d6".parse().unwrap(),
+//!     name: Some("Back Porch".into()),
+//!     date: "2019-11-01T12:00:00Z".parse().unwrap(),
+//!     temp_c: 20.0,
+//!     temp_f: 68.0,
+//!     plausible: true,
+//!     humidity: Some(45.0),
+//!     pressure: None,
+//!     battery: None,
+//! };
+//!
+//! assert_eq!(
+//!     influx_line(&event),
+//!     "temperature,address=f4d55889b1d6,name=Back\\ Porch \
+//!      temp_c=20,temp_f=68,plausible=true,humidity=45 1572609600\n"
+//! );
+//! assert_eq!(
+//!     graphite_lines("home", &event),
+//!     "home.f4d55889b1d6.temp_c 20 1572609600\n\
+//!      home.f4d55889b1d6.temp_f 68 1572609600\n\
+//!      home.f4d55889b1d6.humidity 45 1572609600\n"
+//! );
+//! ```
+
+use crate::events::{Broadcaster, MeasurementEvent};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::net::TcpStream;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+
+/// How many measurements each sink can fall behind by before new ones are dropped.
+pub const QUEUE_SIZE: usize = 10_000;
+
+/// The most measurements to send to a sink at once.
+const BATCH_SIZE: usize = 500;
+
+/// How long to wait before retrying the first time, and the longest to wait after that.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How long to wait for a sink to answer before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why measurements couldn't be written to a sink.
+#[derive(Debug)]
+pub enum SinkError {
+    /// The sink couldn't be reached, or stopped answering.
+    Unreachable(String),
+    /// The sink answered, but refused the measurements.
+    Refused(u16, String),
+}
+
+impl SinkError {
+    /// Whether trying again might help. A sink that refuses measurements will keep refusing them.
+    fn is_retryable(&self) -> bool {
+        match self {
+            SinkError::Unreachable(_) => true,
+            SinkError::Refused(status, _) => *status >= 500,
+        }
+    }
+}
+
+impl std::fmt::Display for SinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            SinkError::Unreachable(e) => write!(f, "The sink could not be reached: {}", e),
+            SinkError::Refused(status, body) => {
+                write!(
+                    f,
+                    "The sink refused the measurements ({}): {}",
+                    status, body
+                )
+            }
+        }
+    }
+}
+
+/// Somewhere to write measurements to.
+pub trait Sink: Send {
+    /// Write a batch of measurements.
+    fn send(&mut self, events: &[MeasurementEvent]) -> Result<(), SinkError>;
+}
+
+/// An InfluxDB 1.x `/write` endpoint (or anything else that takes line protocol).
+pub struct InfluxSink {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl InfluxSink {
+    /// Write to a URL like `http://localhost:8086/write?db=temperatures`. A username and password
+    /// in the URL are sent with each request. Timestamps are always sent in seconds, so any
+    /// `precision` in the URL is replaced.
+    pub fn new(mut url: Url) -> Self {
+        // Measurements are only stored to the second, so there's no point in saying more.
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(name, _)| name != "precision")
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(pairs)
+            .append_pair("precision", "s");
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+
+        InfluxSink { client, url }
+    }
+}
+
+impl Sink for InfluxSink {
+    fn send(&mut self, events: &[MeasurementEvent]) -> Result<(), SinkError> {
+        let body: String = events.iter().map(influx_line).collect();
+
+        let mut request = self.client.post(self.url.as_str()).body(body);
+        if !self.url.username().is_empty() {
+            request = request.basic_auth(self.url.username(), self.url.password());
+        }
+        let mut response = request
+            .send()
+            .map_err(|e| SinkError::Unreachable(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(SinkError::Refused(
+                status.as_u16(),
+                response.text().unwrap_or_default(),
+            ))
+        }
+    }
+}
+
+/// A Graphite plaintext listener, like carbon's port 2003.
+pub struct GraphiteSink {
+    address: String,
+    prefix: String,
+    /// The connection, once there is one. It's made again after anything goes wrong with it.
+    stream: Option<TcpStream>,
+}
+
+impl GraphiteSink {
+    /// Write to a `host:port`, with every metric name starting with `prefix`.
+    pub fn new(address: String, prefix: String) -> Self {
+        GraphiteSink {
+            address,
+            prefix,
+            stream: None,
+        }
+    }
+}
+
+impl Sink for GraphiteSink {
+    fn send(&mut self, events: &[MeasurementEvent]) -> Result<(), SinkError> {
+        let body: String = events
+            .iter()
+            .map(|event| graphite_lines(&self.prefix, event))
+            .collect();
+
+        // Writing to a connection that Graphite has closed succeeds the first time, and the
+        // measurements are lost, so check that it's still open first.
+        if self.stream.as_ref().is_some_and(is_closed) {
+            self.stream = None;
+        }
+        let stream = match self.stream {
+            Some(ref mut stream) => stream,
+            None => {
+                let stream = TcpStream::connect(&self.address)
+                    .map_err(|e| SinkError::Unreachable(e.to_string()))?;
+                let _ = stream.set_write_timeout(Some(TIMEOUT));
+                self.stream.get_or_insert(stream)
+            }
+        };
+
+        if let Err(e) = stream.write_all(body.as_bytes()) {
+            self.stream = None;
+            return Err(SinkError::Unreachable(e.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Whether the other end of a connection has closed it. Graphite never sends anything, so
+/// anything other than there being nothing to read means the connection is done for.
+fn is_closed(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let closed = match stream.peek(&mut [0]) {
+        Err(ref e) => e.kind() != std::io::ErrorKind::WouldBlock,
+        Ok(_) => true,
+    };
+    closed || stream.set_nonblocking(false).is_err()
+}
+
+/// Write a measurement as a line of InfluxDB line protocol, in the same shape that the
+/// [`line_protocol`](crate::line_protocol) module reads. The temperatures have the device's
+/// calibration applied.
+pub fn influx_line(event: &MeasurementEvent) -> String {
+    let mut line = format!("temperature,address={}", event.address);
+    if let Some(ref name) = event.name {
+        if !name.is_empty() {
+            let _ = write!(line, ",name={}", escape_tag(name));
+        }
+    }
+
+    let _ = write!(
+        line,
+        " temp_c={},temp_f={},plausible={}",
+        event.temp_c, event.temp_f, event.plausible
+    );
+    for (field, value) in channels(event) {
+        let _ = write!(line, ",{}={}", field, value);
+    }
+
+    let _ = writeln!(line, " {}", event.date.timestamp());
+    line
+}
+
+/// Write a measurement as Graphite plaintext, one line per reading, named
+/// `prefix.address.reading` (or just `address.reading` without a prefix).
+pub fn graphite_lines(prefix: &str, event: &MeasurementEvent) -> String {
+    let prefix = prefix.trim_end_matches('.');
+    let prefix = if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{}.", prefix)
+    };
+    let timestamp = event.date.timestamp();
+    let mut lines = String::new();
+    let readings = [("temp_c", event.temp_c), ("temp_f", event.temp_f)];
+    for (reading, value) in readings.iter().cloned().chain(channels(event)) {
+        let _ = writeln!(
+            lines,
+            "{}{}.{} {} {}",
+            prefix, event.address, reading, value, timestamp
+        );
+    }
+    lines
+}
+
+/// The readings other than temperature that a measurement has.
+fn channels(event: &MeasurementEvent) -> Vec<(&'static str, f64)> {
+    let channels = [
+        ("humidity", event.humidity),
+        ("pressure", event.pressure),
+        ("battery", event.battery),
+    ];
+    channels
+        .iter()
+        .filter_map(|&(name, value)| value.map(|value| (name, value)))
+        .collect()
+}
+
+/// Escape the characters that are special in a line protocol tag value. Line breaks (and other
+/// control characters) can't be escaped, so they're replaced with spaces.
+fn escape_tag(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        let c = if c.is_control() { ' ' } else { c };
+        if [',', ' ', '=', '\\'].contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Start forwarding every measurement published from now on to a sink, on its own thread. `name`
+/// is what to call the sink when complaining about it.
+pub fn forward(
+    broadcaster: &Broadcaster,
+    name: &str,
+    sink: impl Sink + 'static,
+) -> thread::JoinHandle<()> {
+    let receiver = broadcaster.subscribe_bounded(name, QUEUE_SIZE);
+    let name = name.to_string();
+    thread::spawn(move || run(&name, sink, receiver))
+}
+
+/// Send measurements to a sink as they arrive, until the broadcaster goes away.
+fn run(name: &str, mut sink: impl Sink, receiver: Receiver<MeasurementEvent>) {
+    // Wait for a measurement, then take whatever else is already waiting along with it.
+    while let Ok(first) = receiver.recv() {
+        let mut batch = vec![first];
+        batch.extend(receiver.try_iter().take(BATCH_SIZE - 1));
+
+        let mut delay = MIN_RETRY_DELAY;
+        let mut failing = false;
+        loop {
+            match sink.send(&batch) {
+                Ok(()) => {
+                    if failing {
+                        eprintln!("{} is accepting measurements again", name);
+                    }
+                    break;
+                }
+                Err(e) if e.is_retryable() => {
+                    // Only complain once per outage.
+                    if !failing {
+                        eprintln!("Could not write measurements to {}, retrying: {}", name, e);
+                    }
+                    failing = true;
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Dropped {} measurements that {} refused: {}",
+                        batch.len(),
+                        name,
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_with_line_breaks() {
+        assert_eq!(escape_tag("Back\nPorch,\r1=2"), "Back\\ Porch\\,\\ 1\\=2");
+    }
+
+    #[test]
+    fn precision_is_replaced() {
+        let url = "http://localhost:8086/write?precision=ms&db=temperatures&precision=ns";
+        let sink = InfluxSink::new(url.parse().unwrap());
+        assert_eq!(
+            sink.url.as_str(),
+            "http://localhost:8086/write?db=temperatures&precision=s"
+        );
+
+        let sink = InfluxSink::new("http://localhost:8086/write".parse().unwrap());
+        assert_eq!(sink.url.as_str(), "http://localhost:8086/write?precision=s");
+    }
+}
@@ -1,4 +1,4 @@
-//! Newtype wrappers for type-safe temperature celsius and fahrenheit readings.
+//! Newtype wrappers for type-safe temperature celsius, fahrenheit, and kelvin readings.
 //!
 //! These newtype wrappers are created so that the typechecker helps us keep Celsius and Fahrenheit
 //! values separate (i.e., we don't accidentally use a Celsius reading where we expect a Fahrenheit
@@ -11,6 +11,29 @@
 //! assert_eq!(degrees_fahrenheit.value(), 32.0);
 //! ```
 //!
+//! Every unit converts to every other unit.
+//!
+//! ```
+//! # use temperature_app::temperature::{Celsius, Fahrenheit, Kelvin};
+//! let boiling: Fahrenheit = 212.0.into();
+//! assert!((Celsius::from(boiling).value() - 100.0).abs() < 1e-9);
+//! assert!((Kelvin::from(boiling).value() - 373.15).abs() < 1e-9);
+//!
+//! let absolute_zero: Kelvin = 0.0.into();
+//! assert!((Celsius::from(absolute_zero).value() - -273.15).abs() < 1e-9);
+//! assert!((Fahrenheit::from(absolute_zero).value() - -459.67).abs() < 1e-9);
+//! ```
+//!
+//! When the unit is only known at runtime (say, because somebody asked for it over GraphQL), use
+//! a [`TemperatureUnit`].
+//!
+//! ```
+//! # use temperature_app::temperature::{Celsius, TemperatureUnit};
+//! let temperature: Celsius = 20.0.into();
+//! assert_eq!(TemperatureUnit::Fahrenheit.value_of(temperature), 68.0);
+//! assert_eq!(TemperatureUnit::Fahrenheit.to_celsius(68.0), temperature);
+//! ```
+//!
 //! ```compile_fail
 //! # use temperature_app::temperature::{Celsius, Fahrenheit};
 //! let degrees_celsius: Celsius = 1.0.into();
@@ -18,9 +41,84 @@
 //! // Shouldn't compile...
 //! let result = degress_fahrenheit + degress_celsius;
 //! ```
+//!
+//! Temperatures are points on a scale, so adding two of them together doesn't mean anything, but
+//! the difference between two temperatures does. Differences get their own types, which convert
+//! between units without the offset that temperatures have.
+//!
+//! ```
+//! # use temperature_app::temperature::{Celsius, CelsiusDelta, Fahrenheit, FahrenheitDelta};
+//! let upstairs: Celsius = 22.0.into();
+//! let basement: Celsius = 17.0.into();
+//! let spread: CelsiusDelta = upstairs - basement;
+//! assert_eq!(spread.value(), 5.0);
+//! assert_eq!(basement + spread, upstairs);
+//!
+//! // A difference of one degree celsius is 1.8 degrees fahrenheit, not 33.8.
+//! let adjustment: CelsiusDelta = 1.0.into();
+//! assert_eq!(FahrenheitDelta::from(adjustment).value(), 1.8);
+//! let adjusted = Fahrenheit::from(basement) + FahrenheitDelta::from(adjustment);
+//! assert!((adjusted.value() - Fahrenheit::from(basement + adjustment).value()).abs() < 1e-9);
+//! ```
+//!
+//! ```compile_fail
+//! # use temperature_app::temperature::Celsius;
+//! let upstairs: Celsius = 22.0.into();
+//! let basement: Celsius = 17.0.into();
+//! // Shouldn't compile...
+//! let nonsense = upstairs + basement;
+//! ```
+//!
+//! Every type can be parsed from a string with a unit suffix (which is converted from), or
+//! without one (which is taken to be in the type's own unit). They display with their unit, and
+//! honor the formatter's precision.
+//!
+//! ```
+//! # use temperature_app::temperature::{Celsius, CelsiusDelta, Fahrenheit, Kelvin};
+//! let temperature: Celsius = "70.7 °F".parse().unwrap();
+//! assert!((temperature.value() - 21.5).abs() < 1e-9);
+//! assert_eq!(format!("{:.1}", temperature), "21.5°C");
+//! assert_eq!("21.5C".parse::<Celsius>().unwrap().value(), 21.5);
+//! assert_eq!("21.5".parse::<Celsius>().unwrap().value(), 21.5);
+//! assert_eq!("294.6K".parse::<Kelvin>().unwrap().to_string(), "294.6K");
+//! assert_eq!(format!("{:.2}", "0c".parse::<Fahrenheit>().unwrap()), "32.00°F");
+//!
+//! // Differences convert without the offset.
+//! assert_eq!("1.8F".parse::<CelsiusDelta>().unwrap().value(), 1.0);
+//!
+//! assert!("21.5 furlongs".parse::<Celsius>().is_err());
+//! assert!("warm".parse::<Celsius>().is_err());
+//! ```
+//!
+//! With serde, they're written as plain numbers in their own unit, but can be read from either a
+//! number or a string with a unit.
+//!
+//! ```
+//! # use temperature_app::temperature::Celsius;
+//! let temperatures: Vec<Celsius> = serde_json::from_str(r#"[21.5, "70.7F", 20]"#).unwrap();
+//! assert!((temperatures[1].value() - 21.5).abs() < 1e-9);
+//! assert_eq!(serde_json::to_string(&temperatures[0]).unwrap(), "21.5");
+//! ```
+//!
+//! Anything can be marked as a temperature, but not every number is one that can exist.
+//!
+//! ```
+//! # use temperature_app::temperature::{Celsius, Kelvin};
+//! assert!(Celsius::from(21.5).validate().is_ok());
+//! assert!(Celsius::from(-500.0).validate().is_err());
+//! assert!(Celsius::from(std::f64::NAN).validate().is_err());
+//! assert!(Kelvin::from(-1.0).validate().is_err());
+//! ```
+//!
+//! Combined with a humidity reading, a temperature also gives the [`dew_point`], the
+//! [`heat_index`] (what the temperature feels like), and the [`absolute_humidity`].
+
+use crate::channels::Humidity;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// Temperature, in degrees celsius
-#[derive(juniper::GraphQLScalarValue, Clone, Copy)]
+#[derive(juniper::GraphQLScalarValue, Debug, Clone, Copy, PartialEq)]
 pub struct Celsius(f64);
 
 impl Celsius {
@@ -28,6 +126,18 @@ impl Celsius {
     pub fn value(&self) -> f64 {
         return self.0;
     }
+
+    /// Make sure that this is a temperature that can actually exist: a finite number, no colder
+    /// than absolute zero.
+    pub fn validate(self) -> Result<Self, TemperatureError> {
+        if !self.0.is_finite() {
+            Err(TemperatureError::NotFinite)
+        } else if self.0 < -KELVIN_OFFSET {
+            Err(TemperatureError::BelowAbsoluteZero(self))
+        } else {
+            Ok(self)
+        }
+    }
 }
 
 impl From<f64> for Celsius {
@@ -44,18 +154,35 @@ impl From<Celsius> for f64 {
     }
 }
 
-impl std::ops::Add for Celsius {
+impl std::ops::Add<CelsiusDelta> for Celsius {
     type Output = Self;
 
-    /// Add two Celsius readings together. This makes sense when we have adjustments stored as
-    /// degrees celsius.
-    fn add(self, rhs: Celsius) -> Self::Output {
+    /// Move a Celsius reading by a number of degrees.
+    fn add(self, rhs: CelsiusDelta) -> Self::Output {
         Celsius(self.0 + rhs.0)
     }
 }
 
+impl std::ops::Sub<CelsiusDelta> for Celsius {
+    type Output = Self;
+
+    /// Move a Celsius reading down by a number of degrees.
+    fn sub(self, rhs: CelsiusDelta) -> Self::Output {
+        Celsius(self.0 - rhs.0)
+    }
+}
+
+impl std::ops::Sub for Celsius {
+    type Output = CelsiusDelta;
+
+    /// How many degrees apart two Celsius readings are.
+    fn sub(self, rhs: Celsius) -> Self::Output {
+        CelsiusDelta(self.0 - rhs.0)
+    }
+}
+
 /// Temperature, in degrees fahrenheit
-#[derive(juniper::GraphQLScalarValue, Clone, Copy)]
+#[derive(juniper::GraphQLScalarValue, Debug, Clone, Copy, PartialEq)]
 pub struct Fahrenheit(f64);
 
 impl Fahrenheit {
@@ -63,6 +190,12 @@ impl Fahrenheit {
     pub fn value(&self) -> f64 {
         return self.0;
     }
+
+    /// Make sure that this is a temperature that can actually exist: a finite number, no colder
+    /// than absolute zero.
+    pub fn validate(self) -> Result<Self, TemperatureError> {
+        Celsius::from(self).validate().map(|_| self)
+    }
 }
 
 impl From<f64> for Fahrenheit {
@@ -72,6 +205,40 @@ impl From<f64> for Fahrenheit {
     }
 }
 
+impl From<Fahrenheit> for f64 {
+    /// Get the f64 value back from this Fahrenheit measurement.
+    fn from(val: Fahrenheit) -> Self {
+        val.0
+    }
+}
+
+impl std::ops::Add<FahrenheitDelta> for Fahrenheit {
+    type Output = Self;
+
+    /// Move a Fahrenheit reading by a number of degrees.
+    fn add(self, rhs: FahrenheitDelta) -> Self::Output {
+        Fahrenheit(self.0 + rhs.0)
+    }
+}
+
+impl std::ops::Sub<FahrenheitDelta> for Fahrenheit {
+    type Output = Self;
+
+    /// Move a Fahrenheit reading down by a number of degrees.
+    fn sub(self, rhs: FahrenheitDelta) -> Self::Output {
+        Fahrenheit(self.0 - rhs.0)
+    }
+}
+
+impl std::ops::Sub for Fahrenheit {
+    type Output = FahrenheitDelta;
+
+    /// How many degrees apart two Fahrenheit readings are.
+    fn sub(self, rhs: Fahrenheit) -> Self::Output {
+        FahrenheitDelta(self.0 - rhs.0)
+    }
+}
+
 impl From<Celsius> for Fahrenheit {
     /// Convert a Celsius value to a Fahrenheit value.
     fn from(val: Celsius) -> Self {
@@ -79,3 +246,593 @@ impl From<Celsius> for Fahrenheit {
         Fahrenheit(val)
     }
 }
+
+impl From<Fahrenheit> for Celsius {
+    /// Convert a Fahrenheit value to a Celsius value.
+    fn from(val: Fahrenheit) -> Self {
+        let val = (val.0 - 32.0) / 1.8;
+        Celsius(val)
+    }
+}
+
+/// The difference between zero kelvin and zero degrees celsius
+const KELVIN_OFFSET: f64 = 273.15;
+
+/// Temperature, in kelvin
+#[derive(juniper::GraphQLScalarValue, Debug, Clone, Copy, PartialEq)]
+pub struct Kelvin(f64);
+
+impl Kelvin {
+    /// Get the f64 value back from this Kelvin measurement.
+    pub fn value(&self) -> f64 {
+        self.0
+    }
+
+    /// Make sure that this is a temperature that can actually exist: a finite number, no colder
+    /// than absolute zero.
+    pub fn validate(self) -> Result<Self, TemperatureError> {
+        Celsius::from(self).validate().map(|_| self)
+    }
+}
+
+impl From<f64> for Kelvin {
+    /// Mark an f64 value as being a reading in Kelvin.
+    fn from(val: f64) -> Self {
+        Kelvin(val)
+    }
+}
+
+impl From<Kelvin> for f64 {
+    /// Get the f64 value back from this Kelvin measurement.
+    fn from(val: Kelvin) -> Self {
+        val.0
+    }
+}
+
+impl From<Celsius> for Kelvin {
+    /// Convert a Celsius value to a Kelvin value.
+    fn from(val: Celsius) -> Self {
+        Kelvin(val.0 + KELVIN_OFFSET)
+    }
+}
+
+impl From<Kelvin> for Celsius {
+    /// Convert a Kelvin value to a Celsius value.
+    fn from(val: Kelvin) -> Self {
+        Celsius(val.0 - KELVIN_OFFSET)
+    }
+}
+
+impl From<Fahrenheit> for Kelvin {
+    /// Convert a Fahrenheit value to a Kelvin value.
+    fn from(val: Fahrenheit) -> Self {
+        Celsius::from(val).into()
+    }
+}
+
+impl From<Kelvin> for Fahrenheit {
+    /// Convert a Kelvin value to a Fahrenheit value.
+    fn from(val: Kelvin) -> Self {
+        Celsius::from(val).into()
+    }
+}
+
+/// A difference between two temperatures, in degrees celsius. (A difference in kelvin is the
+/// same thing.)
+#[derive(juniper::GraphQLScalarValue, Debug, Clone, Copy, PartialEq)]
+pub struct CelsiusDelta(f64);
+
+impl CelsiusDelta {
+    /// Get the f64 value back from this difference.
+    pub fn value(&self) -> f64 {
+        self.0
+    }
+}
+
+impl From<f64> for CelsiusDelta {
+    /// Mark an f64 value as being a difference in degrees celsius.
+    fn from(val: f64) -> Self {
+        CelsiusDelta(val)
+    }
+}
+
+impl From<CelsiusDelta> for f64 {
+    /// Get the f64 value back from this difference.
+    fn from(val: CelsiusDelta) -> Self {
+        val.0
+    }
+}
+
+impl From<FahrenheitDelta> for CelsiusDelta {
+    /// Convert a difference in degrees fahrenheit to degrees celsius. Unlike for temperatures,
+    /// there's no offset: only the size of the degrees differs.
+    fn from(val: FahrenheitDelta) -> Self {
+        CelsiusDelta(val.0 / 1.8)
+    }
+}
+
+impl std::ops::Add for CelsiusDelta {
+    type Output = Self;
+
+    /// Add two differences together.
+    fn add(self, rhs: CelsiusDelta) -> Self::Output {
+        CelsiusDelta(self.0 + rhs.0)
+    }
+}
+
+impl std::ops::Sub for CelsiusDelta {
+    type Output = Self;
+
+    /// Subtract one difference from another.
+    fn sub(self, rhs: CelsiusDelta) -> Self::Output {
+        CelsiusDelta(self.0 - rhs.0)
+    }
+}
+
+impl std::ops::Neg for CelsiusDelta {
+    type Output = Self;
+
+    /// The same difference, in the other direction.
+    fn neg(self) -> Self::Output {
+        CelsiusDelta(-self.0)
+    }
+}
+
+/// A difference between two temperatures, in degrees fahrenheit
+#[derive(juniper::GraphQLScalarValue, Debug, Clone, Copy, PartialEq)]
+pub struct FahrenheitDelta(f64);
+
+impl FahrenheitDelta {
+    /// Get the f64 value back from this difference.
+    pub fn value(&self) -> f64 {
+        self.0
+    }
+}
+
+impl From<f64> for FahrenheitDelta {
+    /// Mark an f64 value as being a difference in degrees fahrenheit.
+    fn from(val: f64) -> Self {
+        FahrenheitDelta(val)
+    }
+}
+
+impl From<FahrenheitDelta> for f64 {
+    /// Get the f64 value back from this difference.
+    fn from(val: FahrenheitDelta) -> Self {
+        val.0
+    }
+}
+
+impl From<CelsiusDelta> for FahrenheitDelta {
+    /// Convert a difference in degrees celsius to degrees fahrenheit. Unlike for temperatures,
+    /// there's no offset: only the size of the degrees differs.
+    fn from(val: CelsiusDelta) -> Self {
+        FahrenheitDelta(val.0 * 1.8)
+    }
+}
+
+impl std::ops::Add for FahrenheitDelta {
+    type Output = Self;
+
+    /// Add two differences together.
+    fn add(self, rhs: FahrenheitDelta) -> Self::Output {
+        FahrenheitDelta(self.0 + rhs.0)
+    }
+}
+
+impl std::ops::Sub for FahrenheitDelta {
+    type Output = Self;
+
+    /// Subtract one difference from another.
+    fn sub(self, rhs: FahrenheitDelta) -> Self::Output {
+        FahrenheitDelta(self.0 - rhs.0)
+    }
+}
+
+impl std::ops::Neg for FahrenheitDelta {
+    type Output = Self;
+
+    /// The same difference, in the other direction.
+    fn neg(self) -> Self::Output {
+        FahrenheitDelta(-self.0)
+    }
+}
+
+/// A unit of temperature, for when the unit isn't known until runtime.
+#[derive(juniper::GraphQLEnum, Debug, Clone, Copy, PartialEq)]
+pub enum TemperatureUnit {
+    /// Degrees celsius
+    Celsius,
+    /// Degrees fahrenheit
+    Fahrenheit,
+    /// Kelvin
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    /// Express a Celsius value in this unit.
+    pub fn value_of(self, val: Celsius) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => val.value(),
+            TemperatureUnit::Fahrenheit => Fahrenheit::from(val).value(),
+            TemperatureUnit::Kelvin => Kelvin::from(val).value(),
+        }
+    }
+
+    /// Convert a value in this unit to a Celsius value.
+    pub fn to_celsius(self, val: f64) -> Celsius {
+        match self {
+            TemperatureUnit::Celsius => Celsius(val),
+            TemperatureUnit::Fahrenheit => Fahrenheit(val).into(),
+            TemperatureUnit::Kelvin => Kelvin(val).into(),
+        }
+    }
+
+    /// Parse a temperature, like `21.5` or `70.7 °F`, into a Celsius value. Numbers without a
+    /// unit are taken to be in this unit.
+    pub fn parse_celsius(self, s: &str) -> Result<Celsius, TemperatureParseError> {
+        let (value, unit) = parse_with_unit(s)?;
+        Ok(unit.unwrap_or(self).to_celsius(value))
+    }
+
+    /// Express a difference in degrees celsius in this unit.
+    pub fn delta_value_of(self, val: CelsiusDelta) -> f64 {
+        match self {
+            TemperatureUnit::Celsius | TemperatureUnit::Kelvin => val.value(),
+            TemperatureUnit::Fahrenheit => FahrenheitDelta::from(val).value(),
+        }
+    }
+
+    /// Convert a difference in this unit to a difference in degrees celsius.
+    pub fn to_celsius_delta(self, val: f64) -> CelsiusDelta {
+        match self {
+            TemperatureUnit::Celsius | TemperatureUnit::Kelvin => CelsiusDelta(val),
+            TemperatureUnit::Fahrenheit => FahrenheitDelta(val).into(),
+        }
+    }
+}
+
+/// Errors for temperatures that can't exist.
+#[derive(Debug)]
+pub enum TemperatureError {
+    /// The temperature was NaN or infinite.
+    NotFinite,
+    /// The temperature was colder than absolute zero.
+    BelowAbsoluteZero(Celsius),
+}
+
+impl std::fmt::Display for TemperatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            TemperatureError::NotFinite => "A temperature must be a finite number".fmt(f),
+            TemperatureError::BelowAbsoluteZero(temperature) => write!(
+                f,
+                "{:.2} is colder than absolute zero ({:.2})",
+                temperature,
+                Celsius(-KELVIN_OFFSET)
+            ),
+        }
+    }
+}
+
+/// What to do with a reading that's outside of a device's plausible range
+#[derive(juniper::GraphQLEnum, Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutOfRangeAction {
+    /// Refuse to store the reading.
+    #[default]
+    Reject,
+    /// Store the reading, but mark it as implausible.
+    Flag,
+}
+
+/// The temperatures that a sensor can believably report, open at either end, and what to do
+/// about readings outside of them.
+///
+/// ```
+/// # use temperature_app::temperature::{OutOfRangeAction, PlausibleRange};
+/// let basement = PlausibleRange {
+///     min: Some(0.0.into()),
+///     max: Some(40.0.into()),
+///     action: OutOfRangeAction::Reject,
+/// };
+/// assert!(basement.contains(18.0.into()));
+/// assert!(!basement.contains(55.0.into()));
+/// ```
+#[derive(juniper::GraphQLObject, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlausibleRange {
+    /// The coldest believable temperature, if there is one
+    pub min: Option<Celsius>,
+    /// The warmest believable temperature, if there is one
+    pub max: Option<Celsius>,
+    /// What to do with readings outside of the range
+    #[serde(default)]
+    pub action: OutOfRangeAction,
+}
+
+impl PlausibleRange {
+    /// Whether the temperature is within the range.
+    pub fn contains(&self, temperature: Celsius) -> bool {
+        self.min.is_none_or(|min| temperature.0 >= min.0)
+            && self.max.is_none_or(|max| temperature.0 <= max.0)
+    }
+}
+
+impl std::fmt::Display for PlausibleRange {
+    /// Describe the range, like `0°C to 40°C`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => write!(f, "{} to {}", min, max),
+            (Some(min), None) => write!(f, "at least {}", min),
+            (None, Some(max)) => write!(f, "at most {}", max),
+            (None, None) => "any temperature".fmt(f),
+        }
+    }
+}
+
+/// The temperature that the air would have to be cooled to for its water vapor to condense,
+/// using the Magnus formula (with the Sonntag coefficients). There is no dew point for perfectly
+/// dry air.
+///
+/// ```
+/// # use temperature_app::temperature::dew_point;
+/// // 20°C at 50% is a dew point of 9.3°C, and 30°C at 70% is 23.9°C.
+/// let dew = dew_point(20.0.into(), 50.0.into()).unwrap();
+/// assert!((dew.value() - 9.3).abs() < 0.05);
+/// let dew = dew_point(30.0.into(), 70.0.into()).unwrap();
+/// assert!((dew.value() - 23.9).abs() < 0.05);
+/// // Saturated air is already at its dew point.
+/// assert!((dew_point(12.0.into(), 100.0.into()).unwrap().value() - 12.0).abs() < 1e-9);
+/// assert!(dew_point(20.0.into(), 0.0.into()).is_none());
+/// ```
+pub fn dew_point(temperature: Celsius, humidity: Humidity) -> Option<Celsius> {
+    const A: f64 = 17.62;
+    const B: f64 = 243.12;
+
+    if humidity.value() <= 0.0 {
+        return None;
+    }
+
+    let gamma = (humidity.value() / 100.0).ln() + A * temperature.0 / (B + temperature.0);
+    Some(Celsius(B * gamma / (A - gamma)))
+}
+
+/// What the temperature feels like once the humidity is taken into account, using the National
+/// Weather Service's heat index equation (the Rothfusz regression, with its adjustments for very
+/// dry and very humid air). Below about 80°F, the simpler Steadman approximation is used, which
+/// stays close to the actual temperature.
+///
+/// ```
+/// # use temperature_app::temperature::{heat_index, Celsius, Fahrenheit};
+/// let feels_like = |temp_f: f64, humidity: f64| {
+///     let temperature = Celsius::from(Fahrenheit::from(temp_f));
+///     Fahrenheit::from(heat_index(temperature, humidity.into())).value().round()
+/// };
+/// // From the NWS heat index chart.
+/// assert_eq!(feels_like(80.0, 40.0), 80.0);
+/// assert_eq!(feels_like(90.0, 60.0), 100.0);
+/// assert_eq!(feels_like(86.0, 90.0), 105.0);
+/// assert_eq!(feels_like(100.0, 50.0), 118.0);
+/// ```
+pub fn heat_index(temperature: Celsius, humidity: Humidity) -> Celsius {
+    let t = Fahrenheit::from(temperature).0;
+    let rh = humidity.value();
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t) / 2.0 < 80.0 {
+        return Fahrenheit(simple).into();
+    }
+
+    let mut index = -42.379 + 2.049_015_23 * t + 10.143_331_27 * rh
+        - 0.224_755_41 * t * rh
+        - 0.006_837_83 * t * t
+        - 0.054_817_17 * rh * rh
+        + 0.001_228_74 * t * t * rh
+        + 0.000_852_82 * t * rh * rh
+        - 0.000_001_99 * t * t * rh * rh;
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        index -= ((13.0 - rh) / 4.0) * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        index += ((rh - 85.0) / 10.0) * ((87.0 - t) / 5.0);
+    }
+
+    Fahrenheit(index).into()
+}
+
+/// How much water vapor the air holds, in grams per cubic meter.
+///
+/// ```
+/// # use temperature_app::temperature::absolute_humidity;
+/// // Saturated air holds 4.8 g/m³ at 0°C, 17.3 g/m³ at 20°C, and 30.4 g/m³ at 30°C.
+/// assert!((absolute_humidity(0.0.into(), 100.0.into()) - 4.8).abs() < 0.05);
+/// assert!((absolute_humidity(20.0.into(), 100.0.into()) - 17.3).abs() < 0.05);
+/// assert!((absolute_humidity(30.0.into(), 100.0.into()) - 30.4).abs() < 0.05);
+/// ```
+pub fn absolute_humidity(temperature: Celsius, humidity: Humidity) -> f64 {
+    // The saturation vapor pressure in hectopascals, times the relative humidity, is the actual
+    // vapor pressure. The ideal gas law (with water's gas constant) turns that into a density.
+    let saturation = 6.112 * (17.67 * temperature.0 / (temperature.0 + 243.5)).exp();
+    saturation * humidity.value() * 2.1674 / (temperature.0 + KELVIN_OFFSET)
+}
+
+/// Errors that can occur when parsing a temperature from a string.
+#[derive(Debug)]
+pub enum TemperatureParseError {
+    /// There was nothing to parse.
+    Empty,
+    /// The part before the unit wasn't a number.
+    InvalidNumber(String),
+    /// The unit wasn't one that we know.
+    UnknownUnit(String),
+}
+
+impl std::fmt::Display for TemperatureParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            TemperatureParseError::Empty => "A temperature can't be empty".fmt(f),
+            TemperatureParseError::InvalidNumber(number) => {
+                write!(f, "\"{}\" is not a number", number)
+            }
+            TemperatureParseError::UnknownUnit(unit) => {
+                write!(f, "\"{}\" is not a temperature unit (use C, F, or K)", unit)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for TemperatureUnit {
+    type Err = TemperatureParseError;
+
+    /// Parse a unit, like `C`, `°F`, or `kelvin`, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "c" | "°c" | "celsius" => Ok(TemperatureUnit::Celsius),
+            "f" | "°f" | "fahrenheit" => Ok(TemperatureUnit::Fahrenheit),
+            "k" | "kelvin" => Ok(TemperatureUnit::Kelvin),
+            _ => Err(TemperatureParseError::UnknownUnit(s.trim().into())),
+        }
+    }
+}
+
+impl std::fmt::Display for TemperatureUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            TemperatureUnit::Celsius => "°C".fmt(f),
+            TemperatureUnit::Fahrenheit => "°F".fmt(f),
+            TemperatureUnit::Kelvin => "K".fmt(f),
+        }
+    }
+}
+
+/// Split a string like `21.5 °C` into its number and its unit, if it has one.
+fn parse_with_unit(s: &str) -> Result<(f64, Option<TemperatureUnit>), TemperatureParseError> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(TemperatureParseError::Empty);
+    }
+
+    // The unit is whatever comes after the last digit (or decimal point).
+    let split = s
+        .rfind(|c: char| c.is_ascii_digit() || c == '.')
+        .map_or(0, |index| index + 1);
+    let (number, unit) = s.split_at(split);
+    let value = match number.trim() {
+        // Without a number at all, report the whole thing rather than an empty string.
+        "" => return Err(TemperatureParseError::InvalidNumber(s.into())),
+        number => number
+            .parse()
+            .map_err(|_| TemperatureParseError::InvalidNumber(number.into()))?,
+    };
+    let unit = match unit.trim() {
+        "" => None,
+        unit => Some(unit.parse()?),
+    };
+
+    Ok((value, unit))
+}
+
+/// Reads a temperature type from either a number (in the type's own unit) or a string with a
+/// unit.
+struct TemperatureVisitor<T>(std::marker::PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for TemperatureVisitor<T>
+where
+    T: From<f64> + std::str::FromStr<Err = TemperatureParseError>,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a temperature, like 21.5 or \"70.7 °F\"")
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<T, E> {
+        Ok(T::from(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<T, E> {
+        Ok(T::from(v as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<T, E> {
+        Ok(T::from(v as f64))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+/// Implement parsing, displaying, and serde for a temperature type. `$convert` turns a value in
+/// any unit into the type.
+macro_rules! temperature_text {
+    ($type:ident, $unit:expr, $suffix:expr, $convert:expr) => {
+        impl std::str::FromStr for $type {
+            type Err = TemperatureParseError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let (value, unit) = parse_with_unit(s)?;
+                let convert: fn(f64, TemperatureUnit) -> $type = $convert;
+                Ok(convert(value, unit.unwrap_or($unit)))
+            }
+        }
+
+        impl std::fmt::Display for $type {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+                match f.precision() {
+                    Some(precision) => write!(f, "{:.*}{}", precision, self.0, $suffix),
+                    None => write!(f, "{}{}", self.0, $suffix),
+                }
+            }
+        }
+
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_f64(self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_any(TemperatureVisitor(std::marker::PhantomData))
+            }
+        }
+    };
+}
+
+temperature_text!(Celsius, TemperatureUnit::Celsius, "°C", |value, unit| {
+    unit.to_celsius(value)
+});
+temperature_text!(
+    Fahrenheit,
+    TemperatureUnit::Fahrenheit,
+    "°F",
+    |value, unit| {
+        match unit {
+            TemperatureUnit::Fahrenheit => Fahrenheit(value),
+            unit => unit.to_celsius(value).into(),
+        }
+    }
+);
+temperature_text!(Kelvin, TemperatureUnit::Kelvin, "K", |value, unit| {
+    match unit {
+        TemperatureUnit::Kelvin => Kelvin(value),
+        unit => unit.to_celsius(value).into(),
+    }
+});
+temperature_text!(
+    CelsiusDelta,
+    TemperatureUnit::Celsius,
+    "°C",
+    |value, unit| { unit.to_celsius_delta(value) }
+);
+temperature_text!(
+    FahrenheitDelta,
+    TemperatureUnit::Fahrenheit,
+    "°F",
+    |value, unit| {
+        match unit {
+            TemperatureUnit::Fahrenheit => FahrenheitDelta(value),
+            unit => unit.to_celsius_delta(value).into(),
+        }
+    }
+);